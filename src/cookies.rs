@@ -0,0 +1,134 @@
+//! Checks Netscape cookie files, as passed to ytarchive with `--cookies`.
+
+use anyhow::{anyhow, bail, Context, Result};
+use chrono::{DateTime, TimeZone, Utc};
+
+/// Cookies that YouTube needs to consider a request logged in.
+const LOGIN_COOKIES: &[&str] = &[
+    "SID",
+    "HSID",
+    "SSID",
+    "APISID",
+    "SAPISID",
+    "__Secure-1PSID",
+    "__Secure-3PSID",
+    "LOGIN_INFO",
+];
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Cookie {
+    pub domain: String,
+    pub name: String,
+    /// `None` for session cookies.
+    pub expires: Option<DateTime<Utc>>,
+}
+
+/// What a cookie file holds for YouTube.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CookieStatus {
+    /// Number of YouTube login cookies in the file.
+    pub login_cookies: usize,
+    /// When the first of the login cookies expires, if any of them does.
+    pub expires: Option<DateTime<Utc>>,
+}
+
+impl CookieStatus {
+    pub fn is_expired(&self, now: DateTime<Utc>) -> bool {
+        self.expires.map(|expires| expires <= now).unwrap_or(false)
+    }
+}
+
+/// Parses the contents of a Netscape cookie file.
+pub fn parse(contents: &str) -> Result<Vec<Cookie>> {
+    let mut cookies = vec![];
+    for (i, line) in contents.lines().enumerate() {
+        // Cookies only sent over HTTP are marked with a prefix that looks like
+        // a comment
+        let line = line.strip_prefix("#HttpOnly_").unwrap_or(line);
+        if line.trim().is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let fields = line.split('\t').collect::<Vec<_>>();
+        if fields.len() != 7 {
+            bail!(
+                "Line {} has {} fields instead of 7, is this a Netscape cookie file?",
+                i + 1,
+                fields.len()
+            );
+        }
+        let expires = fields[4]
+            .trim()
+            .parse::<i64>()
+            .map_err(|_| anyhow!("Line {} has an invalid expiry: {:?}", i + 1, fields[4]))?;
+        let expires = match expires {
+            0 => None,
+            ts => Some(
+                Utc.timestamp_opt(ts, 0)
+                    .single()
+                    .ok_or_else(|| anyhow!("Line {} has an expiry out of range: {}", i + 1, ts))?,
+            ),
+        };
+        cookies.push(Cookie {
+            domain: fields[0].to_string(),
+            name: fields[5].to_string(),
+            expires,
+        });
+    }
+    Ok(cookies)
+}
+
+/// Summarizes the YouTube login cookies in the cookies.
+pub fn status(cookies: &[Cookie]) -> CookieStatus {
+    let login = cookies
+        .iter()
+        .filter(|c| c.domain.trim_start_matches('.').ends_with("youtube.com"))
+        .filter(|c| LOGIN_COOKIES.contains(&c.name.as_str()))
+        .collect::<Vec<_>>();
+    CookieStatus {
+        login_cookies: login.len(),
+        expires: login.iter().filter_map(|c| c.expires).min(),
+    }
+}
+
+/// Reads a cookie file and checks that it can be used to log in to YouTube.
+pub fn check(path: &str) -> Result<CookieStatus> {
+    let contents = std::fs::read_to_string(path)
+        .with_context(|| format!("Failed to read cookie file {}", path))?;
+    let cookies = parse(&contents).with_context(|| format!("Invalid cookie file {}", path))?;
+    let status = status(&cookies);
+    if status.login_cookies == 0 {
+        bail!("Cookie file {} has no YouTube login cookies", path);
+    }
+    Ok(status)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse, status};
+    use chrono::{TimeZone, Utc};
+
+    #[test]
+    fn test_parse_cookies() {
+        let file = "# Netscape HTTP Cookie File\n\
+            \n\
+            .youtube.com\tTRUE\t/\tTRUE\t1700000000\tSAPISID\tabc\n\
+            #HttpOnly_.youtube.com\tTRUE\t/\tTRUE\t1800000000\tSID\tdef\n\
+            .youtube.com\tTRUE\t/\tFALSE\t0\tPREF\tf6=40000000\n\
+            .google.com\tTRUE\t/\tTRUE\t1600000000\tSID\tghi\n";
+        let cookies = parse(file).unwrap();
+        assert_eq!(cookies.len(), 4);
+        assert_eq!(cookies[1].name, "SID");
+        assert_eq!(cookies[2].expires, None);
+
+        let status = status(&cookies);
+        assert_eq!(status.login_cookies, 2);
+        assert_eq!(status.expires, Some(Utc.timestamp(1700000000, 0)));
+        assert!(status.is_expired(Utc.timestamp(1750000000, 0)));
+        assert!(!status.is_expired(Utc.timestamp(1650000000, 0)));
+
+        assert!(parse("youtube.com SID abc").is_err());
+        assert!(parse(".youtube.com\tTRUE\t/\tTRUE\tnever\tSID\tabc").is_err());
+        assert!(parse(".youtube.com\tTRUE\t/\tTRUE\t9223372036854775807\tSID\tabc").is_err());
+    }
+}
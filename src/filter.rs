@@ -0,0 +1,529 @@
+//! A small expression language to decide which videos should be recorded.
+//!
+//! Sample expressions:
+//!
+//!   "(?i)karaoke" and not title ~ "(?i)#shorts" and not title ~ "(?i)rerun"
+//!   keyword = "utawaku" or description ~ "(?i)singing stream"
+//!   live and members_only and scheduled between 12:00 and 18:00
+//!   not (duration > 3h or scheduled within 1h)
+//!
+//! A bare string is a shorthand for `title ~ "..."`. Regular expressions use
+//! the syntax of the `regex` crate, and times are in UTC.
+use crate::youtube::video::InitialPlayerResponse;
+use anyhow::{anyhow, bail, Result};
+use chrono::{DateTime, NaiveTime, Utc};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+use ts_rs::TS;
+
+/// The video details a filter is evaluated against. Any field that is `None`
+/// is unknown, and predicates that depend on it evaluate to unknown too.
+#[derive(Debug, Clone, Default, TS, Serialize, Deserialize)]
+#[ts(export, export_to = "web/src/bindings/")]
+pub struct Video {
+    pub title: String,
+    pub description: Option<String>,
+    #[serde(with = "humantime_serde", default)]
+    #[ts(type = "string | null")]
+    pub duration: Option<Duration>,
+    pub members_only: Option<bool>,
+    /// Whether the video is a live stream that is live or upcoming, as
+    /// opposed to a regular upload.
+    pub live: Option<bool>,
+    pub scheduled_start: Option<DateTime<Utc>>,
+    pub keywords: Option<Vec<String>>,
+}
+
+impl From<&InitialPlayerResponse> for Video {
+    fn from(ipr: &InitialPlayerResponse) -> Self {
+        Self {
+            title: ipr.video_details.title.clone(),
+            description: Some(ipr.video_details.short_description.clone()),
+            duration: ipr.duration(),
+            members_only: Some(ipr.is_members_only()),
+            live: Some(ipr.video_details.is_live || ipr.video_details.is_upcoming),
+            scheduled_start: ipr.start_time(),
+            keywords: Some(ipr.video_details.keywords.clone()),
+        }
+    }
+}
+
+/// A parsed filter expression. Serializes back to its source string.
+#[derive(Debug, Clone)]
+pub struct Filter {
+    source: String,
+    expr: Expr,
+}
+
+#[derive(Debug, Clone)]
+enum Expr {
+    Or(Vec<Expr>),
+    And(Vec<Expr>),
+    Not(Box<Expr>),
+    Match(Field, Regex),
+    Keyword(String),
+    Duration(Comparison, Duration),
+    MembersOnly,
+    Live,
+    ScheduledWithin(Duration),
+    ScheduledBetween(NaiveTime, NaiveTime),
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Field {
+    Title,
+    Description,
+    Keyword,
+    Text,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Comparison {
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    Eq,
+}
+
+impl Filter {
+    pub fn parse(source: &str) -> Result<Self> {
+        let tokens = tokenize(source)?;
+        let mut parser = Parser { tokens, pos: 0 };
+        let expr = parser.parse_or()?;
+        if let Some(token) = parser.peek() {
+            bail!("Unexpected {} after end of expression", token);
+        }
+        Ok(Self {
+            source: source.to_string(),
+            expr,
+        })
+    }
+
+    /// Evaluates the filter. Returns `None` if the result depends on details
+    /// that are missing from the video.
+    pub fn eval(&self, video: &Video) -> Option<bool> {
+        self.expr.eval(video)
+    }
+}
+
+impl std::fmt::Display for Filter {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.source)
+    }
+}
+
+impl Serialize for Filter {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        serializer.serialize_str(&self.source)
+    }
+}
+
+impl<'de> Deserialize<'de> for Filter {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        let s = String::deserialize(deserializer)?;
+        Filter::parse(&s).map_err(|e| serde::de::Error::custom(format!("{}: {}", s, e)))
+    }
+}
+
+/// Returns whether a video matches any of the `include` filters and none of
+/// the `exclude` filters, or `None` if that can't be decided without more
+/// details.
+pub fn matches(include: &[Filter], exclude: &[Filter], video: &Video) -> Option<bool> {
+    let included = any(include.iter().map(|f| f.eval(video)));
+    let excluded = any(exclude.iter().map(|f| f.eval(video)));
+    all([included, excluded.map(|x| !x)].into_iter())
+}
+
+/// Three-valued OR: true if any is true, unknown if any is unknown.
+pub fn any(values: impl Iterator<Item = Option<bool>>) -> Option<bool> {
+    let mut result = Some(false);
+    for value in values {
+        match value {
+            Some(true) => return Some(true),
+            None => result = None,
+            Some(false) => (),
+        }
+    }
+    result
+}
+
+/// Three-valued AND: false if any is false, unknown if any is unknown.
+pub fn all(values: impl Iterator<Item = Option<bool>>) -> Option<bool> {
+    let mut result = Some(true);
+    for value in values {
+        match value {
+            Some(false) => return Some(false),
+            None => result = None,
+            Some(true) => (),
+        }
+    }
+    result
+}
+
+impl Expr {
+    fn eval(&self, video: &Video) -> Option<bool> {
+        match self {
+            Expr::Or(exprs) => any(exprs.iter().map(|e| e.eval(video))),
+            Expr::And(exprs) => all(exprs.iter().map(|e| e.eval(video))),
+            Expr::Not(expr) => expr.eval(video).map(|x| !x),
+            Expr::Match(Field::Title, re) => Some(re.is_match(&video.title)),
+            Expr::Match(Field::Description, re) => {
+                video.description.as_ref().map(|d| re.is_match(d))
+            }
+            Expr::Match(Field::Text, re) => any([
+                Some(re.is_match(&video.title)),
+                video.description.as_ref().map(|d| re.is_match(d)),
+            ]
+            .into_iter()),
+            Expr::Match(Field::Keyword, re) => video
+                .keywords
+                .as_ref()
+                .map(|kws| kws.iter().any(|k| re.is_match(k))),
+            Expr::Keyword(keyword) => video
+                .keywords
+                .as_ref()
+                .map(|kws| kws.iter().any(|k| k.to_lowercase() == *keyword)),
+            Expr::Duration(cmp, value) => video.duration.map(|d| match cmp {
+                Comparison::Lt => d < *value,
+                Comparison::Le => d <= *value,
+                Comparison::Gt => d > *value,
+                Comparison::Ge => d >= *value,
+                Comparison::Eq => d == *value,
+            }),
+            Expr::MembersOnly => video.members_only,
+            Expr::Live => video.live,
+            Expr::ScheduledWithin(within) => {
+                let start = video.scheduled_start?;
+                let within = chrono::Duration::from_std(*within).ok()?;
+                Some(start <= Utc::now() + within)
+            }
+            Expr::ScheduledBetween(from, to) => {
+                let time = video.scheduled_start?.time();
+                Some(if from <= to {
+                    *from <= time && time <= *to
+                } else {
+                    // The window wraps around midnight
+                    *from <= time || time <= *to
+                })
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    LParen,
+    RParen,
+    Tilde,
+    Op(String),
+    Str(String),
+    Word(String),
+}
+
+impl std::fmt::Display for Token {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Token::LParen => write!(f, "'('"),
+            Token::RParen => write!(f, "')'"),
+            Token::Tilde => write!(f, "'~'"),
+            Token::Op(op) => write!(f, "'{}'", op),
+            Token::Str(s) => write!(f, "string {:?}", s),
+            Token::Word(w) => write!(f, "'{}'", w),
+        }
+    }
+}
+
+fn tokenize(source: &str) -> Result<Vec<Token>> {
+    let mut tokens = vec![];
+    let mut chars = source.chars().peekable();
+    while let Some(&c) = chars.peek() {
+        match c {
+            c if c.is_whitespace() => {
+                chars.next();
+            }
+            '(' | ')' | '~' => {
+                chars.next();
+                tokens.push(match c {
+                    '(' => Token::LParen,
+                    ')' => Token::RParen,
+                    _ => Token::Tilde,
+                });
+            }
+            '<' | '>' | '=' => {
+                chars.next();
+                let mut op = c.to_string();
+                if c != '=' && chars.peek() == Some(&'=') {
+                    chars.next();
+                    op.push('=');
+                }
+                tokens.push(Token::Op(op));
+            }
+            '"' | '\'' => {
+                chars.next();
+                let mut s = String::new();
+                loop {
+                    match chars.next() {
+                        Some('\\') => match chars.next() {
+                            // Only escape quotes and backslashes, so regular
+                            // expressions like \d can be written as-is.
+                            Some(e) if e == c || e == '\\' => s.push(e),
+                            Some(e) => {
+                                s.push('\\');
+                                s.push(e);
+                            }
+                            None => bail!("Unterminated string"),
+                        },
+                        Some(e) if e == c => break,
+                        Some(e) => s.push(e),
+                        None => bail!("Unterminated string"),
+                    }
+                }
+                tokens.push(Token::Str(s));
+            }
+            c if c.is_alphanumeric() || c == '_' => {
+                let mut word = String::new();
+                while let Some(&c) = chars.peek() {
+                    if !(c.is_alphanumeric() || c == '_' || c == ':' || c == '.') {
+                        break;
+                    }
+                    word.push(c);
+                    chars.next();
+                }
+                tokens.push(Token::Word(word));
+            }
+            c => bail!("Unexpected character '{}'", c),
+        }
+    }
+    Ok(tokens)
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn next(&mut self) -> Result<Token> {
+        let token = self
+            .tokens
+            .get(self.pos)
+            .cloned()
+            .ok_or_else(|| anyhow!("Unexpected end of expression"))?;
+        self.pos += 1;
+        Ok(token)
+    }
+
+    /// Consumes the next token if it is the given keyword.
+    fn eat_word(&mut self, word: &str) -> bool {
+        match self.peek() {
+            Some(Token::Word(w)) if w == word => {
+                self.pos += 1;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    fn expect_word(&mut self, word: &str) -> Result<()> {
+        match self.next()? {
+            Token::Word(w) if w == word => Ok(()),
+            t => bail!("Expected '{}', found {}", word, t),
+        }
+    }
+
+    fn parse_or(&mut self) -> Result<Expr> {
+        let mut exprs = vec![self.parse_and()?];
+        while self.eat_word("or") {
+            exprs.push(self.parse_and()?);
+        }
+        Ok(match exprs.len() {
+            1 => exprs.remove(0),
+            _ => Expr::Or(exprs),
+        })
+    }
+
+    fn parse_and(&mut self) -> Result<Expr> {
+        let mut exprs = vec![self.parse_not()?];
+        while self.eat_word("and") {
+            exprs.push(self.parse_not()?);
+        }
+        Ok(match exprs.len() {
+            1 => exprs.remove(0),
+            _ => Expr::And(exprs),
+        })
+    }
+
+    fn parse_not(&mut self) -> Result<Expr> {
+        if self.eat_word("not") {
+            return Ok(Expr::Not(Box::new(self.parse_not()?)));
+        }
+        self.parse_atom()
+    }
+
+    fn parse_atom(&mut self) -> Result<Expr> {
+        match self.next()? {
+            Token::LParen => {
+                let expr = self.parse_or()?;
+                match self.next()? {
+                    Token::RParen => Ok(expr),
+                    t => bail!("Expected ')', found {}", t),
+                }
+            }
+            Token::Str(s) => Ok(Expr::Match(Field::Title, parse_regex(&s)?)),
+            Token::Word(w) => match w.as_str() {
+                "title" | "description" | "text" => {
+                    let field = match w.as_str() {
+                        "title" => Field::Title,
+                        "description" => Field::Description,
+                        _ => Field::Text,
+                    };
+                    match self.next()? {
+                        Token::Tilde => Ok(Expr::Match(field, self.parse_regex()?)),
+                        t => bail!("Expected '~' after '{}', found {}", w, t),
+                    }
+                }
+                "keyword" => match self.next()? {
+                    Token::Tilde => Ok(Expr::Match(Field::Keyword, self.parse_regex()?)),
+                    Token::Op(op) if op == "=" => match self.next()? {
+                        Token::Str(s) => Ok(Expr::Keyword(s.to_lowercase())),
+                        t => bail!("Expected a string, found {}", t),
+                    },
+                    t => bail!("Expected '~' or '=' after 'keyword', found {}", t),
+                },
+                "duration" => {
+                    let cmp = match self.next()? {
+                        Token::Op(op) => match op.as_str() {
+                            "<" => Comparison::Lt,
+                            "<=" => Comparison::Le,
+                            ">" => Comparison::Gt,
+                            ">=" => Comparison::Ge,
+                            _ => Comparison::Eq,
+                        },
+                        t => bail!("Expected a comparison after 'duration', found {}", t),
+                    };
+                    Ok(Expr::Duration(cmp, self.parse_duration()?))
+                }
+                "members_only" => Ok(Expr::MembersOnly),
+                "live" => Ok(Expr::Live),
+                "scheduled" => match self.next()? {
+                    Token::Word(w) if w == "within" => {
+                        Ok(Expr::ScheduledWithin(self.parse_duration()?))
+                    }
+                    Token::Word(w) if w == "between" => {
+                        let from = self.parse_time()?;
+                        self.expect_word("and")?;
+                        let to = self.parse_time()?;
+                        Ok(Expr::ScheduledBetween(from, to))
+                    }
+                    t => bail!(
+                        "Expected 'within' or 'between' after 'scheduled', found {}",
+                        t
+                    ),
+                },
+                _ => bail!("Unknown field '{}'", w),
+            },
+            t => bail!("Unexpected {}", t),
+        }
+    }
+
+    fn parse_regex(&mut self) -> Result<Regex> {
+        match self.next()? {
+            Token::Str(s) => parse_regex(&s),
+            t => bail!("Expected a regular expression string, found {}", t),
+        }
+    }
+
+    fn parse_duration(&mut self) -> Result<Duration> {
+        match self.next()? {
+            Token::Word(w) | Token::Str(w) => humantime::parse_duration(&w)
+                .map_err(|e| anyhow!("Invalid duration '{}': {}", w, e)),
+            t => bail!("Expected a duration, found {}", t),
+        }
+    }
+
+    fn parse_time(&mut self) -> Result<NaiveTime> {
+        match self.next()? {
+            Token::Word(w) | Token::Str(w) => NaiveTime::parse_from_str(&w, "%H:%M")
+                .map_err(|e| anyhow!("Invalid time '{}', expected HH:MM: {}", w, e)),
+            t => bail!("Expected a time, found {}", t),
+        }
+    }
+}
+
+fn parse_regex(s: &str) -> Result<Regex> {
+    Regex::new(s).map_err(|e| anyhow!("Invalid regular expression {:?}: {}", s, e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{matches, Filter, Video};
+    use std::time::Duration;
+
+    fn video(title: &str) -> Video {
+        Video {
+            title: title.into(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_parse_filter() {
+        Filter::parse("").expect_err("Should not parse empty expression");
+        Filter::parse("title ~").expect_err("Should require a regex");
+        Filter::parse("title ~ \"(\"").expect_err("Should reject invalid regex");
+        Filter::parse("(\"a\"").expect_err("Should require closing paren");
+        Filter::parse("duration < forever").expect_err("Should reject bad duration");
+        Filter::parse("color ~ \"red\"").expect_err("Should reject unknown field");
+        Filter::parse(r#"title ~ "\d+" and not ("a" or 'b')"#).expect("Should parse");
+        Filter::parse("scheduled between 22:00 and 02:00").expect("Should parse");
+    }
+
+    #[test]
+    fn test_eval_filter() {
+        let f = Filter::parse(r#""(?i)karaoke" and not "(?i)shorts" and not "(?i)rerun""#)
+            .expect("Should parse");
+        assert_eq!(f.eval(&video("Karaoke night!")), Some(true));
+        assert_eq!(f.eval(&video("Karaoke #shorts")), Some(false));
+        assert_eq!(f.eval(&video("【RERUN】karaoke")), Some(false));
+        assert_eq!(f.eval(&video("Minecraft")), Some(false));
+
+        // Unknown details only matter when they can change the result
+        let f = Filter::parse(r#""Karaoke" and duration > 1h"#).expect("Should parse");
+        assert_eq!(f.eval(&video("Minecraft")), Some(false));
+        assert_eq!(f.eval(&video("Karaoke")), None);
+        let mut v = video("Karaoke");
+        v.duration = Some(Duration::from_secs(7200));
+        assert_eq!(f.eval(&v), Some(true));
+
+        let f = Filter::parse(r#"keyword = "Utawaku""#).expect("Should parse");
+        let mut v = video("Singing");
+        v.keywords = Some(vec!["utawaku".into()]);
+        assert_eq!(f.eval(&v), Some(true));
+    }
+
+    #[test]
+    fn test_include_exclude() {
+        let include = vec![Filter::parse("\"Karaoke\"").unwrap()];
+        let exclude = vec![Filter::parse("members_only").unwrap()];
+        assert_eq!(matches(&include, &exclude, &video("Karaoke")), None);
+        assert_eq!(
+            matches(&include, &exclude, &video("Minecraft")),
+            Some(false)
+        );
+        let mut v = video("Karaoke");
+        v.members_only = Some(false);
+        assert_eq!(matches(&include, &exclude, &v), Some(true));
+    }
+}
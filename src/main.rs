@@ -1,6 +1,6 @@
 #[macro_use]
 extern crate log;
-use crate::module::Module;
+use crate::module::{Message, Module, SystemEvent, SystemEventKind};
 use crate::msgbus::MessageBus;
 use anyhow::{anyhow, Result};
 use clap::Parser;
@@ -8,8 +8,12 @@ use std::{process::Command, sync::Arc};
 use tokio::sync::RwLock;
 
 mod config;
+mod cookies;
+mod filter;
 mod module;
 mod msgbus;
+mod network;
+mod output;
 mod youtube;
 
 pub static APP_NAME: &str = concat!(
@@ -89,13 +93,12 @@ async fn main() -> Result<()> {
         .await
         .map_err(|e| anyhow!("Failed to read config file: {}", e))?;
     debug!("{:?}", config);
+    youtube::init(&config)?;
 
     // Make sure ffmpeg and ytarchive are installed
     debug!("Found {}", test_ffmpeg()?);
-    debug!(
-        "Found {}",
-        test_ytarchive(&config.ytarchive.executable_path)?
-    );
+    let ytarchive_version = test_ytarchive(&config.ytarchive.executable_path)?;
+    debug!("Found {}", ytarchive_version);
 
     // Set up message bus
     let mut bus = MessageBus::new(65_536);
@@ -117,8 +120,20 @@ async fn main() -> Result<()> {
     let config = Arc::new(RwLock::new(config));
     let h_scraper = run_module!(bus, module::scraper::RSS::new(config.clone()));
     let h_recorder = run_module!(bus, module::recorder::YTArchive::new(config.clone()));
-    let h_notifier = run_module!(bus, module::notifier::Discord::new(config.clone()));
+    let h_notifier = run_module!(bus, module::notifier::Dispatcher::new(config.clone()));
     let h_webserver = run_module!(bus, module::web::WebServer::new(config.clone()));
+    let h_monitor = run_module!(bus, module::monitor::Monitor::new(config.clone()));
+    let h_postprocess = run_module!(bus, module::postprocess::PostProcessor::new(config.clone()));
+    let h_chat = run_module!(bus, module::chat::ChatRecorder::new(config.clone()));
+
+    // Announce the start
+    bus.add_tx()
+        .send(Message::SystemEvent(SystemEvent::new(
+            SystemEventKind::Started,
+            format!("{} started with {}", APP_NAME, ytarchive_version),
+        )))
+        .await
+        .map_err(|e| anyhow!("Failed to send start event: {}", e))?;
 
     // Listen for signals
     let closer = bus.add_tx();
@@ -128,6 +143,12 @@ async fn main() -> Result<()> {
             .expect("Unable to listen for SIGINT");
 
         info!("Received signal, shutting down");
+        let _ = closer
+            .send(Message::SystemEvent(SystemEvent::new(
+                SystemEventKind::Stopping,
+                format!("{} is shutting down", APP_NAME),
+            )))
+            .await;
         closer.close().await.expect("Failed to close message bus");
     });
 
@@ -142,6 +163,9 @@ async fn main() -> Result<()> {
         h_signal,
         h_bus,
         h_webserver,
+        h_monitor,
+        h_postprocess,
+        h_chat,
     )
     .map(|_| ())
     .map_err(|e| anyhow!("Task errored: {}", e))
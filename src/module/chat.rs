@@ -0,0 +1,375 @@
+use super::{
+    recorder::YTAState, Archived, Message, Module, Notification, RecordingStatus, Task, TaskStatus,
+};
+use crate::{
+    config::Config,
+    msgbus::BusTx,
+    youtube::{self, chat::LiveChat, YouTube},
+};
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use serde_json::json;
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Duration,
+};
+use tokio::{
+    io::AsyncWriteExt,
+    sync::{mpsc, watch, RwLock},
+    task::JoinHandle,
+};
+
+/// How long to wait before trying to open the chat again.
+const RETRY_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Number of failed attempts in a row after which the chat is given up on.
+const MAX_FAILURES: u32 = 10;
+
+/// Records the live chat of streams while they are being recorded, and
+/// places it next to the video once it is archived, before the recording is
+/// handed over to post-processing.
+pub struct ChatRecorder {
+    config: Arc<RwLock<Config>>,
+}
+
+/// The chat of a stream being recorded.
+struct Session {
+    stop: watch::Sender<bool>,
+    handle: JoinHandle<()>,
+    path: PathBuf,
+}
+
+impl Session {
+    /// Stops recording the chat and waits until the file is complete.
+    async fn stop(self) -> PathBuf {
+        let _ = self.stop.send(true);
+        let _ = self.handle.await;
+        self.path
+    }
+}
+
+impl ChatRecorder {
+    async fn start(&self, task: &Task) -> Option<Session> {
+        let (working_directory, min_interval, youtube) = {
+            let config = self.config.read().await;
+            let chat = config.chat.as_ref()?;
+            // The chat is read from the same network as the stream
+            let youtube = youtube::shared().with_network(config.network_for(task.source_channel()));
+            (
+                config.ytarchive.working_directory.clone(),
+                chat.min_interval,
+                youtube,
+            )
+        };
+        let youtube = match youtube {
+            Ok(youtube) => youtube,
+            Err(e) => {
+                error!("[{}] Failed to create client: {:?}", task.video_id, e);
+                return None;
+            }
+        };
+
+        let path = Path::new(&working_directory).join(format!("{}.live_chat.jsonl", task.video_id));
+        let (stop, stop_rx) = watch::channel(false);
+        let handle = tokio::spawn(record(
+            youtube,
+            task.video_id.clone(),
+            path.clone(),
+            min_interval,
+            stop_rx,
+        ));
+        info!(
+            "[{}] Recording live chat to {}",
+            task.video_id,
+            path.display()
+        );
+        Some(Session { stop, handle, path })
+    }
+}
+
+/// Reads the chat until it ends or is stopped, appending every action to the
+/// file as a line of JSON.
+async fn record(
+    youtube: YouTube,
+    video_id: String,
+    path: PathBuf,
+    min_interval: Duration,
+    mut stop: watch::Receiver<bool>,
+) {
+    let mut file = None;
+    let mut chat: Option<LiveChat> = None;
+    let mut failures = 0;
+    let mut wait = Duration::ZERO;
+    loop {
+        tokio::select! {
+            _ = stop.changed() => break,
+            _ = tokio::time::sleep(wait) => (),
+        }
+
+        let res = match &mut chat {
+            Some(chat) => chat.poll().await,
+            None => match LiveChat::connect(youtube.clone(), &video_id).await {
+                Ok(connected) => {
+                    chat = Some(connected);
+                    continue;
+                }
+                Err(e) => Err(e),
+            },
+        };
+        let page = match res {
+            Ok(Some(page)) => page,
+            Ok(None) => {
+                info!("[{}] Live chat ended", video_id);
+                break;
+            }
+            Err(e) => {
+                failures += 1;
+                warn!(
+                    "[{}] Failed to read live chat ({}/{}): {:?}",
+                    video_id, failures, MAX_FAILURES, e
+                );
+                if failures >= MAX_FAILURES {
+                    break;
+                }
+                wait = RETRY_INTERVAL;
+                continue;
+            }
+        };
+        failures = 0;
+        wait = page.timeout.max(min_interval);
+
+        if page.actions.is_empty() {
+            continue;
+        }
+        if let Err(e) = append(&mut file, &path, page.actions).await {
+            error!("[{}] Failed to write live chat: {:?}", video_id, e);
+            break;
+        }
+    }
+
+    if let Some(mut file) = file {
+        let _ = file.flush().await;
+    }
+}
+
+/// Appends the actions to the file, opening it if needed.
+async fn append(
+    file: &mut Option<tokio::fs::File>,
+    path: &Path,
+    actions: Vec<serde_json::Value>,
+) -> Result<()> {
+    if file.is_none() {
+        *file = Some(
+            tokio::fs::OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(path)
+                .await
+                .with_context(|| format!("Failed to open {:?}", path))?,
+        );
+    }
+    let file = file.as_mut().expect("file was just opened");
+
+    let received_at = chrono::Utc::now();
+    let mut lines = String::new();
+    for action in actions {
+        lines.push_str(&json!({ "received_at": received_at, "action": action }).to_string());
+        lines.push('\n');
+    }
+    file.write_all(lines.as_bytes())
+        .await
+        .with_context(|| format!("Failed to write {:?}", path))
+}
+
+/// Moves the chat next to the archived video, keeping its name, and returns
+/// where it went.
+async fn place(chat: PathBuf, archived: &Archived) -> Result<Option<PathBuf>> {
+    if !chat.exists() {
+        return Ok(None);
+    }
+    let dest = Path::new(&archived.output_file).with_extension("live_chat.jsonl");
+    if tokio::fs::rename(&chat, &dest).await.is_err() {
+        tokio::fs::copy(&chat, &dest)
+            .await
+            .with_context(|| format!("Failed to copy live chat to {:?}", dest))?;
+        tokio::fs::remove_file(&chat)
+            .await
+            .with_context(|| format!("Failed to remove {:?}", chat))?;
+    }
+    info!(
+        "[{}] Moved live chat to {}",
+        archived.task.video_id,
+        dest.display()
+    );
+    Ok(Some(dest))
+}
+
+#[async_trait]
+impl Module for ChatRecorder {
+    fn new(config: Arc<RwLock<Config>>) -> Self {
+        Self { config }
+    }
+
+    async fn run(&self, tx: &BusTx<Message>, rx: &mut mpsc::Receiver<Message>) -> Result<()> {
+        let mut sessions: HashMap<String, Session> = HashMap::new();
+
+        while let Some(msg) = rx.recv().await {
+            match msg {
+                Message::RecordingStatus(RecordingStatus { task, status }) => {
+                    match status.state() {
+                        YTAState::Recording => {
+                            if sessions.contains_key(&task.video_id) {
+                                continue;
+                            }
+                            if let Some(session) = self.start(&task).await {
+                                sessions.insert(task.video_id.clone(), session);
+                            }
+                        }
+                        YTAState::AlreadyProcessed
+                        | YTAState::Ended
+                        | YTAState::Interrupted
+                        | YTAState::LoginRequired
+                        | YTAState::Errored => {
+                            // The recording didn't finish, so keep the chat in
+                            // the working directory
+                            if let Some(session) = sessions.remove(&task.video_id) {
+                                tokio::spawn(async move {
+                                    let path = session.stop().await;
+                                    warn!(
+                                        "[{}] Recording stopped, live chat kept at {}",
+                                        task.video_id,
+                                        path.display()
+                                    );
+                                });
+                            }
+                        }
+                        _ => (),
+                    }
+                }
+                // The recording couldn't be moved into its output directory
+                Message::ToNotify(Notification {
+                    task,
+                    status: TaskStatus::Failed,
+                    ..
+                }) => {
+                    if let Some(session) = sessions.remove(&task.video_id) {
+                        tokio::spawn(async move {
+                            let path = session.stop().await;
+                            warn!(
+                                "[{}] Recording failed, live chat kept at {}",
+                                task.video_id,
+                                path.display()
+                            );
+                        });
+                    }
+                }
+                Message::Archived(mut archived) => {
+                    let session = sessions.remove(&archived.task.video_id);
+                    let tx = tx.clone();
+                    tokio::spawn(async move {
+                        if let Some(session) = session {
+                            let path = session.stop().await;
+                            match place(path, &archived).await {
+                                Ok(Some(dest)) => {
+                                    archived.sidecars.push(dest.to_string_lossy().into_owned())
+                                }
+                                Ok(None) => (),
+                                Err(e) => error!(
+                                    "[{}] Failed to move live chat: {:?}",
+                                    archived.task.video_id, e
+                                ),
+                            }
+                        }
+                        let _ = tx.send(Message::Finalized(archived)).await;
+                    });
+                }
+                _ => (),
+            }
+        }
+
+        // Finish writing the chats that are still being recorded
+        for (_, session) in sessions {
+            session.stop().await;
+        }
+        debug!("Chat module finished");
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{place, ChatRecorder};
+    use crate::config::Config;
+    use crate::module::{recorder::test_dir, Archived, Message, Module, Task};
+    use crate::msgbus::MessageBus;
+    use std::sync::Arc;
+    use tokio::sync::{mpsc, RwLock};
+
+    fn archived(output_file: String) -> Archived {
+        Archived {
+            task: Task {
+                title: "Karaoke".into(),
+                video_id: "abc".into(),
+                video_picture: "".into(),
+                channel_name: "Moona".into(),
+                channel_id: "UCP0BspO_AMEe3aQqqpo89Dg".into(),
+                channel_picture: None,
+                output_directory: "".into(),
+                scheduled_start: None,
+                recorder: Default::default(),
+                source_channel_id: None,
+            },
+            output_file,
+            sidecars: vec!["Karaoke [abc].info.json".into()],
+        }
+    }
+
+    #[tokio::test]
+    async fn test_place() {
+        let dir = test_dir("chat");
+        let chat = dir.join("abc.live_chat.jsonl");
+        let video = dir.join("Karaoke [abc].mp4");
+        let archived = archived(video.to_string_lossy().into_owned());
+
+        // Streams without chat
+        assert_eq!(place(chat.clone(), &archived).await.unwrap(), None);
+
+        std::fs::write(&chat, "{}\n").unwrap();
+        let dest = place(chat.clone(), &archived).await.unwrap().unwrap();
+        assert_eq!(dest, dir.join("Karaoke [abc].live_chat.jsonl"));
+        assert_eq!(std::fs::read_to_string(&dest).unwrap(), "{}\n");
+        assert!(!chat.exists());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_finalized() {
+        let config: Config = toml::from_str(include_str!("../../config.example.toml")).unwrap();
+        let module = ChatRecorder::new(Arc::new(RwLock::new(config)));
+        let mut bus = MessageBus::<Message>::new(10);
+        let tx = bus.add_tx();
+        let mut bus_rx = bus.add_rx();
+        tokio::spawn(async move { bus.start().await });
+
+        // Recordings are handed over to post-processing, even without a chat
+        let (module_tx, mut module_rx) = mpsc::channel(10);
+        module_tx
+            .send(Message::Archived(archived("Karaoke [abc].mp4".into())))
+            .await
+            .unwrap();
+        drop(module_tx);
+        module.run(&tx, &mut module_rx).await.unwrap();
+
+        match bus_rx.recv().await {
+            Some(Message::Finalized(finalized)) => {
+                assert_eq!(finalized.output_file, "Karaoke [abc].mp4");
+                assert_eq!(finalized.sidecars, ["Karaoke [abc].info.json"]);
+            }
+            other => panic!("Expected a finalized recording, got {:?}", other),
+        }
+        tx.close().await.unwrap();
+    }
+}
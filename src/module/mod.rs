@@ -1,5 +1,8 @@
 use self::recorder::YTAStatus;
-use crate::{config::Config, msgbus::BusTx};
+use crate::{
+    config::{Config, RecorderOverrides},
+    msgbus::BusTx,
+};
 use anyhow::Result;
 use async_trait::async_trait;
 use serde::{Deserialize, Serialize};
@@ -7,7 +10,10 @@ use std::{fmt::Debug, sync::Arc};
 use tokio::sync::{mpsc, RwLock};
 use ts_rs::TS;
 
+pub mod chat;
+pub mod monitor;
 pub mod notifier;
+pub mod postprocess;
 pub mod recorder;
 pub mod scraper;
 pub mod web;
@@ -18,6 +24,15 @@ pub enum Message {
     ToRecord(Task),
     ToNotify(Notification),
     RecordingStatus(RecordingStatus),
+    Duplicate(Duplicate),
+    Scheduled(ScheduledTask),
+    SystemEvent(SystemEvent),
+    Archived(Archived),
+    /// An archived recording along with its live chat, ready to be
+    /// post-processed. Sent by the chat module for every `Archived` one.
+    Finalized(Archived),
+    PostProcess(PostProcessStatus),
+    Relocated(Relocated),
 }
 
 #[derive(Debug, Clone, TS, Serialize, Deserialize)]
@@ -30,13 +45,50 @@ pub struct Task {
     pub channel_id: String,
     pub channel_picture: Option<String>,
     pub output_directory: String,
+    /// When the stream is scheduled to start, if known.
+    #[serde(default)]
+    pub scheduled_start: Option<chrono::DateTime<chrono::Utc>>,
+    /// Recorder settings for this task, taking precedence over those of the
+    /// channel and the `ytarchive` config.
+    #[serde(flatten, default)]
+    pub recorder: RecorderOverrides,
+    /// The configured channel that queued the task, whose settings apply to
+    /// it. Only set when it isn't the video's channel, e.g. for collabs found
+    /// by a mention.
+    #[serde(default)]
+    pub source_channel_id: Option<String>,
 }
 
-#[derive(Debug, Clone, TS)]
+impl Task {
+    /// Returns the ID of the configured channel whose settings apply to the
+    /// task.
+    pub fn source_channel(&self) -> &str {
+        self.source_channel_id
+            .as_deref()
+            .unwrap_or(&self.channel_id)
+    }
+}
+
+#[derive(Debug, Clone, TS, Serialize, Deserialize)]
 #[ts(export, export_to = "web/src/bindings/")]
 pub struct Notification {
     pub task: Task,
     pub status: TaskStatus,
+    /// Latest ytarchive status of the recording, if it was started.
+    pub recording: Option<recorder::YTAStatus>,
+    /// Final path of the recorded file, once it is done.
+    pub output_file: Option<String>,
+    /// Size of the recorded file in bytes, once it is done.
+    pub output_size: Option<u64>,
+}
+
+/// A task held back until shortly before its stream starts.
+#[derive(Debug, Clone, TS, Serialize)]
+#[ts(export, export_to = "web/src/bindings/")]
+pub struct ScheduledTask {
+    pub task: Task,
+    /// When ytarchive will be started for the task.
+    pub spawn_at: chrono::DateTime<chrono::Utc>,
 }
 
 #[derive(Debug, Clone, TS)]
@@ -46,7 +98,127 @@ pub struct RecordingStatus {
     pub status: YTAStatus,
 }
 
-#[derive(Debug, Clone, PartialEq, TS)]
+/// A recording that was moved into its output directory.
+#[derive(Debug, Clone, TS, Serialize)]
+#[ts(export, export_to = "web/src/bindings/")]
+pub struct Archived {
+    pub task: Task,
+    pub output_file: String,
+    /// Files written next to the recording, such as `.info.json`.
+    pub sidecars: Vec<String>,
+}
+
+/// A file of a recording that post-processing renamed or removed, e.g. by
+/// remuxing or uploading it.
+#[derive(Debug, Clone, TS, Serialize)]
+#[ts(export, export_to = "web/src/bindings/")]
+pub struct Relocated {
+    pub video_id: String,
+    pub from: String,
+    /// The new path of the file, or `None` if it was removed.
+    pub to: Option<String>,
+}
+
+/// Progress of a post-processing step on a recording.
+#[derive(Debug, Clone, TS, Serialize)]
+#[ts(export, export_to = "web/src/bindings/")]
+pub struct PostProcessStatus {
+    pub task: Task,
+    /// Name of the step, or its type if it has no name.
+    pub step: String,
+    pub state: PostProcessState,
+    /// What the step did, or why it failed.
+    pub message: Option<String>,
+    pub timestamp: chrono::DateTime<chrono::Utc>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, TS, Serialize)]
+#[serde(rename_all = "snake_case")]
+#[ts(export, export_to = "web/src/bindings/")]
+pub enum PostProcessState {
+    Running,
+    Done,
+    Failed,
+}
+
+/// A task that was not recorded because the video was already requested.
+#[derive(Debug, Clone, TS, Serialize)]
+#[ts(export, export_to = "web/src/bindings/")]
+pub struct Duplicate {
+    pub task: Task,
+    pub reason: DuplicateReason,
+    pub timestamp: chrono::DateTime<chrono::Utc>,
+}
+
+#[derive(Debug, Clone, PartialEq, TS, Serialize)]
+#[ts(export, export_to = "web/src/bindings/")]
+pub enum DuplicateReason {
+    /// The video is already being recorded into the same directory.
+    Active,
+    /// The video is already being recorded, and will also be placed into the
+    /// task's output directory when done.
+    Merged,
+    /// The video was already recorded into the same directory.
+    Recorded,
+    /// The video was already recorded, and was placed into the task's output
+    /// directory.
+    Linked,
+}
+
+/// Something that happened to hoshinova itself rather than to a task, such as
+/// a scraper failing repeatedly or the disk running full.
+#[derive(Debug, Clone, TS, Serialize, Deserialize)]
+#[ts(export, export_to = "web/src/bindings/")]
+pub struct SystemEvent {
+    pub kind: SystemEventKind,
+    pub message: String,
+    pub timestamp: chrono::DateTime<chrono::Utc>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, TS, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+#[ts(export, export_to = "web/src/bindings/")]
+pub enum SystemEventKind {
+    Started,
+    Stopping,
+    ScraperFailing,
+    ScraperRecovered,
+    DiskSpaceLow,
+    YtarchiveMissing,
+    YtarchiveChanged,
+    ConfigReloadFailed,
+    PostProcessFailed,
+    CookiesExpired,
+}
+
+impl SystemEvent {
+    pub fn new(kind: SystemEventKind, message: impl Into<String>) -> Self {
+        Self {
+            kind,
+            message: message.into(),
+            timestamp: chrono::Utc::now(),
+        }
+    }
+}
+
+impl SystemEventKind {
+    pub fn title(&self) -> &'static str {
+        match self {
+            SystemEventKind::Started => "Started",
+            SystemEventKind::Stopping => "Shutting down",
+            SystemEventKind::ScraperFailing => "Scraper failing",
+            SystemEventKind::ScraperRecovered => "Scraper recovered",
+            SystemEventKind::DiskSpaceLow => "Low disk space",
+            SystemEventKind::YtarchiveMissing => "ytarchive missing",
+            SystemEventKind::YtarchiveChanged => "ytarchive changed",
+            SystemEventKind::ConfigReloadFailed => "Config reload failed",
+            SystemEventKind::PostProcessFailed => "Post-processing failed",
+            SystemEventKind::CookiesExpired => "Cookies expired",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash, TS)]
 #[ts(export, export_to = "web/src/bindings/")]
 pub enum TaskStatus {
     Waiting,
@@ -55,6 +227,13 @@ pub enum TaskStatus {
     Failed,
 }
 
+impl TaskStatus {
+    /// Returns whether this is the last status of a task.
+    pub fn is_final(&self) -> bool {
+        matches!(self, TaskStatus::Done | TaskStatus::Failed)
+    }
+}
+
 impl Serialize for TaskStatus {
     fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
     where
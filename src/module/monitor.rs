@@ -0,0 +1,275 @@
+use super::{Message, Module, SystemEvent, SystemEventKind};
+use crate::{config::Config, cookies, msgbus::BusTx};
+use anyhow::Result;
+use async_trait::async_trait;
+use std::{collections::HashSet, sync::Arc};
+use tokio::sync::{mpsc, RwLock};
+
+/// Periodically checks the free disk space, the ytarchive executable and the
+/// cookie files, and reports problems as system events.
+pub struct Monitor {
+    config: Arc<RwLock<Config>>,
+}
+
+/// What the previous checks found, so each problem is only reported once.
+#[derive(Default)]
+struct State {
+    ytarchive_version: Option<String>,
+    ytarchive_missing: bool,
+    low_space: HashSet<String>,
+    expired_cookies: HashSet<String>,
+}
+
+impl State {
+    /// Records the result of running ytarchive, and returns an event if it
+    /// went missing, came back or changed versions.
+    fn ytarchive(&mut self, version: Result<String>) -> Option<SystemEvent> {
+        let version = match version {
+            Ok(version) => version,
+            Err(e) => {
+                if self.ytarchive_missing {
+                    return None;
+                }
+                self.ytarchive_missing = true;
+                return Some(SystemEvent::new(
+                    SystemEventKind::YtarchiveMissing,
+                    format!("{:#}", e),
+                ));
+            }
+        };
+
+        let event = if self.ytarchive_missing {
+            self.ytarchive_missing = false;
+            Some(SystemEvent::new(
+                SystemEventKind::YtarchiveChanged,
+                format!("ytarchive is available again: {}", version),
+            ))
+        } else {
+            match &self.ytarchive_version {
+                Some(old) if *old != version => Some(SystemEvent::new(
+                    SystemEventKind::YtarchiveChanged,
+                    format!("ytarchive changed from {} to {}", old, version),
+                )),
+                _ => None,
+            }
+        };
+        self.ytarchive_version = Some(version);
+        event
+    }
+
+    /// Records the problem with a cookie file, if any, and returns an event if
+    /// it's a new one.
+    fn cookies(&mut self, path: String, problem: Option<String>) -> Option<SystemEvent> {
+        match problem {
+            Some(problem) => self
+                .expired_cookies
+                .insert(path)
+                .then(|| SystemEvent::new(SystemEventKind::CookiesExpired, problem)),
+            None => {
+                self.expired_cookies.remove(&path);
+                None
+            }
+        }
+    }
+
+    /// Records the free space of a directory, and returns an event if it just
+    /// dropped below the minimum.
+    fn space(&mut self, dir: String, available: u64, min_free_space: u64) -> Option<SystemEvent> {
+        if available >= min_free_space {
+            self.low_space.remove(&dir);
+            return None;
+        }
+        if !self.low_space.insert(dir.clone()) {
+            return None;
+        }
+        Some(SystemEvent::new(
+            SystemEventKind::DiskSpaceLow,
+            format!(
+                "Only {} free in {}",
+                bytesize::ByteSize(available).to_string_as(true),
+                dir
+            ),
+        ))
+    }
+}
+
+impl Monitor {
+    async fn check(&self, state: &mut State) -> Vec<SystemEvent> {
+        let mut events = vec![];
+        let (executable, min_free_space, directories, cookie_files) = {
+            let config = self.config.read().await;
+            (
+                config.ytarchive.executable_path.clone(),
+                config.monitor.min_free_space,
+                config
+                    .directories()
+                    .into_iter()
+                    .map(String::from)
+                    .collect::<Vec<_>>(),
+                config
+                    .cookie_files()
+                    .into_iter()
+                    .map(String::from)
+                    .collect::<Vec<_>>(),
+            )
+        };
+
+        // Check that ytarchive can still be run
+        let version = tokio::task::spawn_blocking(move || crate::test_ytarchive(&executable))
+            .await
+            .unwrap_or_else(|e| Err(e.into()));
+        events.extend(state.ytarchive(version));
+
+        // Check that the cookies can still be used
+        for path in cookie_files {
+            let problem = match cookies::check(&path) {
+                Ok(status) if status.is_expired(chrono::Utc::now()) => {
+                    Some(format!("The cookies in {} have expired", path))
+                }
+                Ok(_) => None,
+                Err(e) => Some(format!("{:#}", e)),
+            };
+            events.extend(state.cookies(path, problem));
+        }
+
+        // Check the free space of every directory
+        let min_free_space = match min_free_space {
+            Some(min) => min,
+            None => return events,
+        };
+        for dir in directories {
+            let available = match fs2::available_space(&dir) {
+                Ok(available) => available,
+                Err(e) => {
+                    debug!("Failed to get free space of {}: {}", dir, e);
+                    continue;
+                }
+            };
+            events.extend(state.space(dir, available, min_free_space.as_u64()));
+        }
+        events
+    }
+}
+
+#[async_trait]
+impl Module for Monitor {
+    fn new(config: Arc<RwLock<Config>>) -> Self {
+        Self { config }
+    }
+
+    async fn run(&self, tx: &BusTx<Message>, rx: &mut mpsc::Receiver<Message>) -> Result<()> {
+        let mut state = State::default();
+        loop {
+            for event in self.check(&mut state).await {
+                warn!("{}: {}", event.kind.title(), event.message);
+                if tx.send(Message::SystemEvent(event)).await.is_err() {
+                    return Ok(());
+                }
+            }
+
+            // Sleep until the next check, or until the bus closes
+            let wakeup = tokio::time::Instant::now() + self.config.read().await.monitor.interval;
+            loop {
+                tokio::select! {
+                    message = rx.recv() => if message.is_none() {
+                        debug!("Monitor module finished");
+                        return Ok(());
+                    },
+                    _ = tokio::time::sleep_until(wakeup) => break,
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::State;
+    use crate::module::{SystemEvent, SystemEventKind};
+    use anyhow::anyhow;
+
+    fn kind(event: Option<SystemEvent>) -> Option<SystemEventKind> {
+        event.map(|e| e.kind)
+    }
+
+    #[test]
+    fn test_ytarchive() {
+        let mut state = State::default();
+        let version = |v: &str| Ok(v.to_string());
+
+        // The first check only records the version
+        assert_eq!(kind(state.ytarchive(version("0.3.2"))), None);
+        assert_eq!(kind(state.ytarchive(version("0.3.2"))), None);
+
+        let event = state.ytarchive(version("0.4.0")).unwrap();
+        assert_eq!(event.kind, SystemEventKind::YtarchiveChanged);
+        assert_eq!(event.message, "ytarchive changed from 0.3.2 to 0.4.0");
+
+        // Going missing is only reported once
+        let event = state.ytarchive(Err(anyhow!("not found"))).unwrap();
+        assert_eq!(event.kind, SystemEventKind::YtarchiveMissing);
+        assert_eq!(event.message, "not found");
+        assert_eq!(kind(state.ytarchive(Err(anyhow!("not found")))), None);
+
+        // Coming back is reported, without also reporting a version change
+        let event = state.ytarchive(version("0.4.1")).unwrap();
+        assert_eq!(event.kind, SystemEventKind::YtarchiveChanged);
+        assert_eq!(event.message, "ytarchive is available again: 0.4.1");
+        assert_eq!(kind(state.ytarchive(version("0.4.1"))), None);
+
+        // Missing from the start
+        let mut state = State::default();
+        assert_eq!(
+            kind(state.ytarchive(Err(anyhow!("not found")))),
+            Some(SystemEventKind::YtarchiveMissing)
+        );
+        assert_eq!(
+            kind(state.ytarchive(version("0.3.2"))),
+            Some(SystemEventKind::YtarchiveChanged)
+        );
+    }
+
+    #[test]
+    fn test_space() {
+        let mut state = State::default();
+        let gib = 1024 * 1024 * 1024;
+        let mut space =
+            |dir: &str, available: u64| kind(state.space(dir.into(), available, 10 * gib));
+
+        assert_eq!(space("/videos", 20 * gib), None);
+        // Only the first check below the minimum is reported, per directory
+        assert_eq!(
+            space("/videos", 5 * gib),
+            Some(SystemEventKind::DiskSpaceLow)
+        );
+        assert_eq!(space("/videos", 4 * gib), None);
+        assert_eq!(space("/temp", 4 * gib), Some(SystemEventKind::DiskSpaceLow));
+        // Until there's enough space again
+        assert_eq!(space("/videos", 10 * gib), None);
+        assert_eq!(
+            space("/videos", 5 * gib),
+            Some(SystemEventKind::DiskSpaceLow)
+        );
+        assert_eq!(space("/temp", 4 * gib), None);
+    }
+
+    #[test]
+    fn test_cookies() {
+        let mut state = State::default();
+        let mut cookies = |problem: Option<&str>| {
+            kind(state.cookies("cookies.txt".into(), problem.map(String::from)))
+        };
+
+        assert_eq!(cookies(None), None);
+        assert_eq!(
+            cookies(Some("expired")),
+            Some(SystemEventKind::CookiesExpired)
+        );
+        assert_eq!(cookies(Some("expired")), None);
+        assert_eq!(cookies(None), None);
+        assert_eq!(
+            cookies(Some("expired")),
+            Some(SystemEventKind::CookiesExpired)
+        );
+    }
+}
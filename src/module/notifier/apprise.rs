@@ -0,0 +1,76 @@
+use super::template::{self, Defaults};
+use super::{Notification, Notifier, ResponseExt, TaskStatus};
+use crate::config::NotifierAppriseConfig;
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use reqwest::Client;
+use serde::Serialize;
+
+/// Sends notifications through an Apprise API server, which supports a large
+/// number of services through Apprise URLs.
+pub struct Apprise(pub NotifierAppriseConfig);
+
+const DEFAULTS: Defaults = Defaults {
+    title: "{{status_title}}",
+    body: "{{task.title}}\n{{task.channel_name}}\n{{video_url}}",
+};
+
+#[derive(Serialize)]
+struct AppriseNotify<'a> {
+    #[serde(skip_serializing_if = "<[_]>::is_empty")]
+    urls: &'a [String],
+    title: String,
+    body: String,
+}
+
+#[async_trait]
+impl Notifier for Apprise {
+    fn name(&self) -> String {
+        format!("Apprise server {}", self.0.api_url)
+    }
+
+    fn notify_on(&self) -> &[TaskStatus] {
+        &self.0.notify_on
+    }
+
+    async fn send(&self, client: &Client, notification: &Notification) -> Result<()> {
+        let rendered = template::render(self.0.template.as_ref(), &DEFAULTS, None, notification)?;
+        let message = AppriseNotify {
+            urls: &self.0.urls,
+            title: rendered.title,
+            body: rendered.body,
+        };
+        self.post(client, &message).await
+    }
+
+    async fn send_message(&self, client: &Client, title: &str, body: &str) -> Result<()> {
+        let message = AppriseNotify {
+            urls: &self.0.urls,
+            title: title.into(),
+            body: body.into(),
+        };
+        self.post(client, &message).await
+    }
+}
+
+impl Apprise {
+    async fn post(&self, client: &Client, message: &AppriseNotify<'_>) -> Result<()> {
+        // Either use the URLs stored on the server under a key, or send them
+        // along with the notification
+        let api_url = self.0.api_url.trim_end_matches('/');
+        let url = match &self.0.key {
+            Some(key) => format!("{}/notify/{}", api_url, key),
+            None => format!("{}/notify", api_url),
+        };
+        client
+            .post(url)
+            .json(message)
+            .send()
+            .await
+            .context("Failed to send Apprise notification")?
+            .check()
+            .await
+            .context("Apprise returned error")?;
+        Ok(())
+    }
+}
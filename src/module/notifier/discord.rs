@@ -0,0 +1,352 @@
+use super::template::{self, human_size, Defaults};
+use super::{
+    channel_url, Notification, Notifier, ResponseExt, SystemEvent, SystemEventKind, TaskStatus,
+};
+use crate::{config::NotifierDiscordConfig, APP_NAME};
+use anyhow::{anyhow, Context, Result};
+use async_trait::async_trait;
+use lazy_static::lazy_static;
+use reqwest::{Client, StatusCode};
+use serde::{Deserialize, Serialize};
+use std::{collections::HashMap, sync::Mutex, time::Duration};
+
+pub struct Discord(pub NotifierDiscordConfig);
+
+lazy_static! {
+    static ref MESSAGES: Mutex<Messages> = Mutex::new(Messages::default());
+}
+
+/// Discord only allows a few requests per webhook every couple of seconds, so
+/// the progress isn't edited more often than this.
+const MIN_PROGRESS_INTERVAL: Duration = Duration::from_secs(10);
+
+/// IDs of the messages posted for each task, keyed by webhook URL and video
+/// ID, so they can be edited when the status changes.
+#[derive(Default)]
+struct Messages(HashMap<(String, String), String>);
+
+impl Messages {
+    /// Returns the ID of the message to edit for the task, or `None` if a new
+    /// one should be posted.
+    fn existing(&self, key: &(String, String)) -> Option<String> {
+        self.0.get(key).cloned()
+    }
+
+    /// Remembers the message sent for the task, unless it was about its last
+    /// status, as it won't be edited anymore.
+    fn sent(&mut self, key: (String, String), id: String, last: bool) {
+        if last {
+            self.0.remove(&key);
+        } else {
+            self.0.insert(key, id);
+        }
+    }
+
+    fn forget(&mut self, key: &(String, String)) {
+        self.0.remove(key);
+    }
+}
+
+const DEFAULTS: Defaults = Defaults {
+    title: "{{status_title}}",
+    body: "[{{task.title}}]({{video_url}})",
+};
+
+#[derive(Deserialize)]
+struct PostedMessage {
+    id: String,
+}
+
+#[derive(Serialize)]
+struct WebhookMessage {
+    content: String,
+    embeds: Vec<DiscordEmbed>,
+}
+
+#[derive(Serialize)]
+struct DiscordEmbed {
+    title: String,
+    description: String,
+    color: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    author: Option<DiscordEmbedAuthor>,
+    footer: DiscordEmbedFooter,
+    timestamp: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    thumbnail: Option<DiscordEmbedThumbnail>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    fields: Vec<DiscordEmbedField>,
+}
+
+#[derive(Serialize)]
+struct DiscordEmbedField {
+    name: String,
+    value: String,
+    inline: bool,
+}
+
+#[derive(Serialize)]
+struct DiscordEmbedAuthor {
+    name: String,
+    url: String,
+    icon_url: Option<String>,
+}
+
+#[derive(Serialize)]
+struct DiscordEmbedFooter {
+    text: String,
+}
+
+#[derive(Serialize)]
+struct DiscordEmbedThumbnail {
+    url: String,
+}
+
+#[async_trait]
+impl Notifier for Discord {
+    fn name(&self) -> String {
+        "Discord webhook".into()
+    }
+
+    fn notify_on(&self) -> &[TaskStatus] {
+        &self.0.notify_on
+    }
+
+    async fn send(&self, client: &Client, notification: &Notification) -> Result<()> {
+        let task = &notification.task;
+        let template = self.0.template.as_ref();
+        let color = template
+            .and_then(|t| t.colors.get(&notification.status).copied())
+            .unwrap_or(match notification.status {
+                TaskStatus::Waiting => 0xebd045,
+                TaskStatus::Recording => 0x58b9ff,
+                TaskStatus::Done => 0x45eb45,
+                TaskStatus::Failed => 0xeb4545,
+            });
+        let timestamp = chrono::Utc::now().to_rfc3339();
+        let rendered = template::render(template, &DEFAULTS, None, notification)?;
+
+        // Construct the payload
+        let message = WebhookMessage {
+            content: rendered.content.unwrap_or_default(),
+            embeds: vec![DiscordEmbed {
+                title: rendered.title,
+                description: rendered.body,
+                color,
+                author: Some(DiscordEmbedAuthor {
+                    name: task.channel_name.clone(),
+                    url: channel_url(notification),
+                    icon_url: task.channel_picture.clone(),
+                }),
+                footer: DiscordEmbedFooter {
+                    text: APP_NAME.into(),
+                },
+                timestamp,
+                thumbnail: Some(DiscordEmbedThumbnail {
+                    url: task.video_picture.clone(),
+                }),
+                fields: if self.0.edit_in_place {
+                    fields(notification)
+                } else {
+                    vec![]
+                },
+            }],
+        };
+
+        if self.0.edit_in_place {
+            return self.send_or_edit(client, notification, &message).await;
+        }
+        self.post(client, &message).await
+    }
+
+    async fn send_event(&self, client: &Client, event: &SystemEvent) -> Result<()> {
+        let color = match event.kind {
+            SystemEventKind::Started | SystemEventKind::ScraperRecovered => 0x45eb45,
+            SystemEventKind::Stopping | SystemEventKind::YtarchiveChanged => 0x58b9ff,
+            _ => 0xeb4545,
+        };
+        let message = WebhookMessage {
+            content: "".into(),
+            embeds: vec![DiscordEmbed {
+                title: event.kind.title().into(),
+                description: event.message.clone(),
+                color,
+                author: None,
+                footer: DiscordEmbedFooter {
+                    text: APP_NAME.into(),
+                },
+                timestamp: event.timestamp.to_rfc3339(),
+                thumbnail: None,
+                fields: vec![],
+            }],
+        };
+        self.post(client, &message).await
+    }
+
+    fn progress_interval(&self) -> Option<Duration> {
+        if self.0.edit_in_place {
+            self.0
+                .progress_interval
+                .map(|interval| interval.max(MIN_PROGRESS_INTERVAL))
+        } else {
+            None
+        }
+    }
+
+    fn forget(&self, video_id: &str) {
+        let key = (self.0.webhook_url.clone(), video_id.to_owned());
+        MESSAGES.lock().unwrap().forget(&key);
+    }
+}
+
+impl Discord {
+    async fn post(&self, client: &Client, message: &WebhookMessage) -> Result<()> {
+        client
+            .post(&self.0.webhook_url)
+            .header("Content-Type", "application/json")
+            .json(message)
+            .send()
+            .await
+            .context("Failed to send Discord webhook")?
+            .check()
+            .await
+            .context("Discord webhook returned error")?;
+        Ok(())
+    }
+
+    /// Edits the message previously posted for the task, or posts a new one
+    /// and remembers its ID.
+    async fn send_or_edit(
+        &self,
+        client: &Client,
+        notification: &Notification,
+        message: &WebhookMessage,
+    ) -> Result<()> {
+        let key = (
+            self.0.webhook_url.clone(),
+            notification.task.video_id.clone(),
+        );
+        let last = notification.status.is_final();
+        let existing = MESSAGES.lock().unwrap().existing(&key);
+
+        if let Some(id) = existing {
+            let res = client
+                .patch(self.url(Some(&id), false)?)
+                .json(message)
+                .send()
+                .await
+                .context("Failed to edit Discord webhook message")?;
+
+            // Post a new message if the old one was deleted
+            if res.status() != StatusCode::NOT_FOUND {
+                res.check()
+                    .await
+                    .context("Discord webhook returned error")?;
+                MESSAGES.lock().unwrap().sent(key, id, last);
+                return Ok(());
+            }
+            debug!("Discord message {} was deleted, posting a new one", id);
+        }
+
+        let posted: PostedMessage = client
+            .post(self.url(None, true)?)
+            .json(message)
+            .send()
+            .await
+            .context("Failed to send Discord webhook")?
+            .check()
+            .await
+            .context("Discord webhook returned error")?
+            .json()
+            .await
+            .context("Failed to parse Discord webhook response")?;
+
+        MESSAGES.lock().unwrap().sent(key, posted.id, last);
+        Ok(())
+    }
+
+    /// Returns the URL of the webhook, or of one of its messages. Query
+    /// parameters such as `thread_id` are kept.
+    fn url(&self, message_id: Option<&str>, wait: bool) -> Result<url::Url> {
+        let mut url =
+            url::Url::parse(&self.0.webhook_url).context("Invalid Discord webhook URL")?;
+        if let Some(id) = message_id {
+            url.path_segments_mut()
+                .map_err(|_| anyhow!("Invalid Discord webhook URL"))?
+                .pop_if_empty()
+                .extend(&["messages", id]);
+        }
+        if wait {
+            url.query_pairs_mut().append_pair("wait", "true");
+        }
+        Ok(url)
+    }
+}
+
+/// Builds embed fields showing the progress of the recording.
+fn fields(notification: &Notification) -> Vec<DiscordEmbedField> {
+    let mut fields = vec![];
+    let mut add = |name: &str, value: String| {
+        fields.push(DiscordEmbedField {
+            name: name.into(),
+            value,
+            inline: true,
+        })
+    };
+
+    if let Some(recording) = &notification.recording {
+        if let Some(quality) = recording.video_quality() {
+            add("Quality", quality.into());
+        }
+        if notification.status == TaskStatus::Recording {
+            if let (Some(video), Some(audio)) =
+                (recording.video_fragments(), recording.audio_fragments())
+            {
+                add("Fragments", format!("{} video / {} audio", video, audio));
+            }
+            if let Some(size) = recording.total_size() {
+                add("Downloaded", size.into());
+            }
+        }
+    }
+    if let Some(size) = notification.output_size {
+        add("File size", human_size(size));
+    }
+    fields
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Messages;
+
+    #[test]
+    fn test_messages() {
+        let mut messages = Messages::default();
+        let key = |url: &str, video_id: &str| (url.to_string(), video_id.to_string());
+
+        // The first status is posted, later ones edit it
+        assert_eq!(messages.existing(&key("a", "v1")), None);
+        messages.sent(key("a", "v1"), "1".into(), false);
+        assert_eq!(messages.existing(&key("a", "v1")), Some("1".into()));
+        messages.sent(key("a", "v1"), "1".into(), false);
+        assert_eq!(messages.existing(&key("a", "v1")), Some("1".into()));
+
+        // Each webhook and video has its own message
+        assert_eq!(messages.existing(&key("b", "v1")), None);
+        assert_eq!(messages.existing(&key("a", "v2")), None);
+
+        // A message posted because the old one was deleted replaces it
+        messages.sent(key("a", "v1"), "2".into(), false);
+        assert_eq!(messages.existing(&key("a", "v1")), Some("2".into()));
+
+        // The last status is still an edit, after which the message is dropped
+        messages.sent(key("a", "v2"), "3".into(), false);
+        messages.sent(key("a", "v2"), "3".into(), true);
+        assert_eq!(messages.existing(&key("a", "v2")), None);
+
+        // Also when the last status isn't sent to the webhook
+        messages.forget(&key("a", "v1"));
+        assert_eq!(messages.existing(&key("a", "v1")), None);
+        assert!(messages.0.is_empty());
+    }
+}
@@ -0,0 +1,65 @@
+use super::template::{self, Defaults};
+use super::{video_url, Notification, Notifier, ResponseExt, TaskStatus};
+use crate::config::NotifierGotifyConfig;
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use reqwest::Client;
+use serde_json::json;
+
+/// Sends notifications to a Gotify server.
+pub struct Gotify(pub NotifierGotifyConfig);
+
+const DEFAULTS: Defaults = Defaults {
+    title: "{{status_title}}",
+    body: "{{task.title}}\n{{task.channel_name}}\n{{video_url}}",
+};
+
+#[async_trait]
+impl Notifier for Gotify {
+    fn name(&self) -> String {
+        format!("Gotify server {}", self.0.url)
+    }
+
+    fn notify_on(&self) -> &[TaskStatus] {
+        &self.0.notify_on
+    }
+
+    async fn send(&self, client: &Client, notification: &Notification) -> Result<()> {
+        let rendered = template::render(self.0.template.as_ref(), &DEFAULTS, None, notification)?;
+        let url = video_url(notification);
+        let message = json!({
+            "title": rendered.title,
+            "message": rendered.body,
+            "priority": self.0.priority,
+            "extras": {
+                "client::notification": { "click": { "url": url } },
+            },
+        });
+        self.post(client, &message).await
+    }
+
+    async fn send_message(&self, client: &Client, title: &str, body: &str) -> Result<()> {
+        let message = json!({
+            "title": title,
+            "message": body,
+            "priority": self.0.priority,
+        });
+        self.post(client, &message).await
+    }
+}
+
+impl Gotify {
+    async fn post(&self, client: &Client, message: &serde_json::Value) -> Result<()> {
+        client
+            .post(format!("{}/message", self.0.url.trim_end_matches('/')))
+            .header("X-Gotify-Key", &self.0.token)
+            .json(message)
+            .send()
+            .await
+            .context("Failed to send Gotify message")?
+            .check()
+            .await
+            .context("Gotify returned error")?;
+        Ok(())
+    }
+}
@@ -0,0 +1,112 @@
+use super::queue::{delivery_id, Payload};
+use super::template::{self, Defaults};
+use super::{Notification, Notifier, ResponseExt, SystemEvent, TaskStatus};
+use crate::config::NotifierMatrixConfig;
+use anyhow::{anyhow, Context, Result};
+use async_trait::async_trait;
+use reqwest::Client;
+use serde::Serialize;
+
+/// Sends notifications to a Matrix room.
+pub struct Matrix(pub NotifierMatrixConfig);
+
+/// Defaults for the plain text body.
+const DEFAULTS: Defaults = Defaults {
+    title: "{{status_title}}",
+    body: "{{task.title}}\n{{task.channel_name}}\n{{video_url}}",
+};
+
+/// Defaults for the HTML body.
+const DEFAULTS_HTML: Defaults = Defaults {
+    title: "{{status_title}}",
+    body: "<a href=\"{{video_url}}\">{{task.title}}</a><br>{{task.channel_name}}",
+};
+
+#[derive(Serialize)]
+struct RoomMessage {
+    msgtype: &'static str,
+    body: String,
+    format: &'static str,
+    formatted_body: String,
+}
+
+fn escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+#[async_trait]
+impl Notifier for Matrix {
+    fn name(&self) -> String {
+        format!("Matrix room {}", self.0.room_id)
+    }
+
+    fn notify_on(&self) -> &[TaskStatus] {
+        &self.0.notify_on
+    }
+
+    async fn send(&self, client: &Client, notification: &Notification) -> Result<()> {
+        let payload = Payload::Task(Box::new(notification.clone()));
+        self.deliver(client, &delivery_id(), &payload).await
+    }
+
+    async fn send_event(&self, client: &Client, event: &SystemEvent) -> Result<()> {
+        self.deliver(client, &delivery_id(), &Payload::Event(event.clone()))
+            .await
+    }
+
+    /// Uses the ID of the delivery as the transaction ID, so the homeserver
+    /// ignores retries of a message it already received.
+    async fn deliver(&self, client: &Client, id: &str, payload: &Payload) -> Result<()> {
+        let message = match payload {
+            Payload::Task(notification) => {
+                let template = self.0.template.as_ref();
+                let plain = template::render(template, &DEFAULTS, None, notification)?;
+                let html = template::render(template, &DEFAULTS_HTML, Some(escape), notification)?;
+                RoomMessage {
+                    msgtype: "m.text",
+                    body: format!("{}: {}", plain.title, plain.body),
+                    format: "org.matrix.custom.html",
+                    formatted_body: format!("<b>{}</b>: {}", html.title, html.body),
+                }
+            }
+            Payload::Event(event) => {
+                let title = event.kind.title();
+                RoomMessage {
+                    msgtype: "m.text",
+                    body: format!("{}: {}", title, event.message),
+                    format: "org.matrix.custom.html",
+                    formatted_body: format!("<b>{}</b>: {}", title, escape(&event.message)),
+                }
+            }
+        };
+        self.post(client, &message, id).await
+    }
+}
+
+impl Matrix {
+    async fn post(&self, client: &Client, message: &RoomMessage, txn_id: &str) -> Result<()> {
+        // Room IDs contain reserved characters, so let the URL library encode
+        // the path segments
+        let mut url = url::Url::parse(&self.0.homeserver).context("Invalid homeserver URL")?;
+        url.path_segments_mut()
+            .map_err(|_| anyhow!("Invalid homeserver URL"))?
+            .pop_if_empty()
+            .extend(&["_matrix", "client", "v3", "rooms", self.0.room_id.as_str()])
+            .extend(&["send", "m.room.message", txn_id]);
+
+        client
+            .put(url)
+            .bearer_auth(&self.0.access_token)
+            .json(message)
+            .send()
+            .await
+            .context("Failed to send Matrix message")?
+            .check()
+            .await
+            .context("Matrix homeserver returned error")?;
+        Ok(())
+    }
+}
@@ -0,0 +1,768 @@
+use super::{
+    recorder::YTAState, Message, Module, Notification, SystemEvent, SystemEventKind, TaskStatus,
+};
+use crate::msgbus::BusTx;
+use crate::{
+    config::{ChannelConfig, Config, NotifierConfig, NotifierDeliveryConfig},
+    network,
+};
+use anyhow::Result;
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use queue::{Delivery, Payload, Queue};
+use reqwest::{Client, StatusCode};
+use std::{
+    collections::HashMap,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+use tokio::sync::{mpsc, RwLock};
+
+mod apprise;
+mod discord;
+mod gotify;
+mod matrix;
+mod ntfy;
+mod queue;
+mod slack;
+mod telegram;
+mod template;
+mod webhook;
+
+/// A notification target, such as a Discord webhook or a Telegram chat.
+#[async_trait]
+pub trait Notifier: Send + Sync {
+    /// Name of the target, used in logs.
+    fn name(&self) -> String;
+
+    /// Statuses the target wants to be notified about.
+    fn notify_on(&self) -> &[TaskStatus];
+
+    async fn send(&self, client: &Client, notification: &Notification) -> Result<()>;
+
+    /// Sends a queued notification or system event. The ID stays the same
+    /// when the delivery is retried, for targets that can use it to drop
+    /// repeated requests.
+    async fn deliver(&self, client: &Client, _id: &str, payload: &Payload) -> Result<()> {
+        match payload {
+            Payload::Task(notification) => self.send(client, notification).await,
+            Payload::Event(event) => self.send_event(client, event).await,
+        }
+    }
+
+    /// Sends a system event, by default as a message titled after the kind of
+    /// event.
+    async fn send_event(&self, client: &Client, event: &SystemEvent) -> Result<()> {
+        self.send_message(client, event.kind.title(), &event.message)
+            .await
+    }
+
+    /// Sends a message with a title and a plain text body, escaping them as
+    /// needed. Only used by the default `send_event`.
+    async fn send_message(&self, _client: &Client, _title: &str, _body: &str) -> Result<()> {
+        Err(anyhow::anyhow!(
+            "{} doesn't support plain messages",
+            self.name()
+        ))
+    }
+
+    /// How often the target wants to receive the progress of a recording, as
+    /// `Recording` notifications. Only useful for targets that update their
+    /// messages in place.
+    fn progress_interval(&self) -> Option<Duration> {
+        None
+    }
+
+    /// Drops anything the target keeps about the video, once it won't be
+    /// notified about it anymore.
+    fn forget(&self, _video_id: &str) {}
+}
+
+/// A configured notifier, along with the name channels refer to it by.
+struct Target {
+    /// Identifies the target in the delivery queue, e.g. `discord#0`.
+    id: String,
+    name: Option<String>,
+    /// System events the target wants to be notified about.
+    events: Vec<SystemEventKind>,
+    notifier: Box<dyn Notifier>,
+}
+
+impl Target {
+    /// Returns whether the target wants the notification, given the config of
+    /// the channel it is about, if any.
+    fn wants(&self, notification: &Notification, channel: Option<&ChannelConfig>) -> bool {
+        let routed = match (channel.and_then(|c| c.notifiers.as_ref()), &self.name) {
+            (Some(names), Some(name)) => names.contains(name),
+            (Some(_), None) => false,
+            (None, name) => name.is_none(),
+        };
+        let status = &notification.status;
+        routed
+            && self.notifier.notify_on().contains(status)
+            && channel
+                .and_then(|c| c.notify_on.as_ref())
+                .map(|notify_on| notify_on.contains(status))
+                .unwrap_or(true)
+    }
+}
+
+/// Builds the notification targets from the configuration.
+fn targets(cfg: &NotifierConfig) -> Vec<Target> {
+    let mut targets = vec![];
+    macro_rules! add {
+        ($field:ident, $backend:path) => {
+            for (i, c) in cfg.$field.iter().enumerate() {
+                targets.push(Target {
+                    id: format!("{}#{}", stringify!($field), i),
+                    name: c.name.clone(),
+                    events: c.events.clone(),
+                    notifier: Box::new($backend(c.clone())),
+                });
+            }
+        };
+    }
+    add!(discord, discord::Discord);
+    add!(webhook, webhook::Webhook);
+    add!(slack, slack::Slack);
+    add!(telegram, telegram::Telegram);
+    add!(matrix, matrix::Matrix);
+    add!(ntfy, ntfy::Ntfy);
+    add!(gotify, gotify::Gotify);
+    add!(apprise, apprise::Apprise);
+    targets
+}
+
+/// Error returned when a target asks to be retried after some time.
+#[derive(Debug)]
+pub struct RateLimited(pub Duration);
+
+impl std::fmt::Display for RateLimited {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Rate limited, retry after {:?}", self.0)
+    }
+}
+
+impl std::error::Error for RateLimited {}
+
+/// Limit on how long a single notification may take to send.
+const SEND_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Used when a target is rate limited without saying for how long.
+const DEFAULT_RETRY_AFTER: Duration = Duration::from_secs(30);
+
+#[async_trait]
+trait ResponseExt: Sized {
+    /// Like `error_for_status`, but returns `RateLimited` for 429 responses.
+    async fn check(self) -> Result<Self>;
+}
+
+#[async_trait]
+impl ResponseExt for reqwest::Response {
+    async fn check(self) -> Result<Self> {
+        if self.status() != StatusCode::TOO_MANY_REQUESTS {
+            return Ok(self.error_for_status()?);
+        }
+
+        let header = self
+            .headers()
+            .get(reqwest::header::RETRY_AFTER)
+            .and_then(|v| v.to_str().ok())
+            .and_then(parse_retry_after);
+        let retry_after = match header {
+            Some(retry_after) => Some(retry_after),
+            // Discord and Telegram also put it in the body, in seconds
+            None => self
+                .json::<serde_json::Value>()
+                .await
+                .ok()
+                .and_then(|body| {
+                    body["retry_after"]
+                        .as_f64()
+                        .or_else(|| body["parameters"]["retry_after"].as_f64())
+                        .and_then(from_secs)
+                }),
+        };
+        Err(RateLimited(retry_after.unwrap_or(DEFAULT_RETRY_AFTER)).into())
+    }
+}
+
+/// Removes the URL from a request error, for targets with secrets in it.
+fn without_url(e: anyhow::Error) -> anyhow::Error {
+    match e.downcast::<reqwest::Error>() {
+        Ok(e) => e.without_url().into(),
+        Err(e) => e,
+    }
+}
+
+/// Parses a Retry-After header, which is either in seconds or an HTTP date.
+fn parse_retry_after(value: &str) -> Option<Duration> {
+    if let Ok(secs) = value.trim().parse::<f64>() {
+        return from_secs(secs);
+    }
+    let date = DateTime::parse_from_rfc2822(value.trim()).ok()?;
+    Some(
+        (date.with_timezone(&Utc) - Utc::now())
+            .to_std()
+            .unwrap_or(Duration::ZERO),
+    )
+}
+
+fn from_secs(secs: f64) -> Option<Duration> {
+    if secs.is_finite() && secs >= 0.0 {
+        Some(Duration::from_secs_f64(secs))
+    } else {
+        None
+    }
+}
+
+/// Returns how long to wait before retrying after the given number of failed
+/// attempts.
+fn backoff(cfg: &NotifierDeliveryConfig, attempts: u32) -> Duration {
+    let factor = 2u32.saturating_pow(attempts.saturating_sub(1));
+    cfg.initial_backoff
+        .saturating_mul(factor)
+        .min(cfg.max_backoff)
+}
+
+/// Returns the title describing a task status.
+fn status_title(status: &TaskStatus) -> &'static str {
+    match status {
+        TaskStatus::Waiting => "Waiting for Live",
+        TaskStatus::Recording => "Recording",
+        TaskStatus::Done => "Done",
+        TaskStatus::Failed => "Failed",
+    }
+}
+
+fn video_url(notification: &Notification) -> String {
+    format!("https://youtu.be/{}", notification.task.video_id)
+}
+
+fn channel_url(notification: &Notification) -> String {
+    format!(
+        "https://www.youtube.com/channel/{}",
+        notification.task.channel_id
+    )
+}
+
+/// When each target was last sent the progress of each video, keyed by target
+/// ID and video ID.
+type ProgressTimes = HashMap<(String, String), Instant>;
+
+/// How often the progress of a recording is checked against the intervals of
+/// the targets, so they aren't rebuilt for every line of ytarchive output.
+const PROGRESS_CHECK_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Returns whether the progress of the video should be checked again, given
+/// when it was last checked, and records the check if so.
+fn progress_due(checked: &mut HashMap<String, Instant>, video_id: &str, now: Instant) -> bool {
+    if let Some(last) = checked.get(video_id) {
+        if now.duration_since(*last) < PROGRESS_CHECK_INTERVAL {
+            return false;
+        }
+    }
+    checked.insert(video_id.to_owned(), now);
+    true
+}
+
+/// Sends task notifications to every configured target, retrying failed
+/// deliveries.
+pub struct Dispatcher {
+    config: Arc<RwLock<Config>>,
+    client: Client,
+}
+
+impl Dispatcher {
+    /// Queues the notification for every target that wants it, and returns
+    /// how many deliveries were queued. For progress notifications, pass when
+    /// each target last got the progress of each video, so they are only sent
+    /// as often as the targets want.
+    async fn enqueue(
+        &self,
+        queue: &mut Queue,
+        notification: Notification,
+        progress: Option<&mut ProgressTimes>,
+    ) -> usize {
+        // Get the targets and the channel from the current configuration
+        let (targets, channel) = {
+            let config = self.config.read().await;
+            let targets = match &config.notifier {
+                Some(cfg) => targets(cfg),
+                None => return 0,
+            };
+            let channel = config.channel(notification.task.source_channel()).cloned();
+            (targets, channel)
+        };
+
+        let progress = match progress {
+            Some(progress) => progress,
+            None => {
+                self.warn_unknown_notifiers(&targets, channel.as_ref(), &notification);
+                return self.enqueue_all(queue, targets, channel.as_ref(), notification);
+            }
+        };
+
+        let mut queued = 0;
+        let now = Instant::now();
+        for target in targets {
+            let interval = match target.notifier.progress_interval() {
+                Some(interval) => interval,
+                None => continue,
+            };
+            if !target.wants(&notification, channel.as_ref()) {
+                continue;
+            }
+            let key = (target.id.clone(), notification.task.video_id.clone());
+            if let Some(last) = progress.get(&key) {
+                if now.duration_since(*last) < interval {
+                    continue;
+                }
+            }
+            progress.insert(key, now);
+            queue.push(Delivery::new(
+                target.id,
+                Payload::Task(Box::new(notification.clone())),
+            ));
+            queued += 1;
+        }
+        queued
+    }
+
+    fn enqueue_all(
+        &self,
+        queue: &mut Queue,
+        targets: Vec<Target>,
+        channel: Option<&ChannelConfig>,
+        notification: Notification,
+    ) -> usize {
+        let mut queued = 0;
+        for target in targets {
+            if target.wants(&notification, channel) {
+                queue.push(Delivery::new(
+                    target.id,
+                    Payload::Task(Box::new(notification.clone())),
+                ));
+                queued += 1;
+            } else {
+                debug!(
+                    "Not notifying {} on status {:?} of {}",
+                    target.notifier.name(),
+                    notification.status,
+                    notification.task.channel_name,
+                );
+                if notification.status.is_final() {
+                    target.notifier.forget(&notification.task.video_id);
+                }
+            }
+        }
+        queued
+    }
+
+    /// Warns about names in the channel config that don't refer to any target.
+    fn warn_unknown_notifiers(
+        &self,
+        targets: &[Target],
+        channel: Option<&ChannelConfig>,
+        notification: &Notification,
+    ) {
+        if let Some(names) = channel.and_then(|c| c.notifiers.as_ref()) {
+            for name in names {
+                if !targets.iter().any(|t| t.name.as_ref() == Some(name)) {
+                    warn!(
+                        "Unknown notifier {:?} for channel {}",
+                        name, notification.task.channel_name
+                    );
+                }
+            }
+        }
+    }
+
+    /// Queues the system event for every target that wants it, and returns
+    /// how many deliveries were queued.
+    async fn enqueue_event(&self, queue: &mut Queue, event: SystemEvent) -> usize {
+        let targets = match &self.config.read().await.notifier {
+            Some(cfg) => targets(cfg),
+            None => return 0,
+        };
+
+        let mut queued = 0;
+        for target in targets {
+            if target.events.contains(&event.kind) {
+                queue.push(Delivery::new(target.id, Payload::Event(event.clone())));
+                queued += 1;
+            }
+        }
+        queued
+    }
+
+    /// Sends the deliveries that are due. Each target receives its
+    /// notifications in order, while different targets are sent to
+    /// concurrently.
+    async fn deliver(&self, queue: &mut Queue) {
+        let (targets, cfg) = match &self.config.read().await.notifier {
+            Some(cfg) => (targets(cfg), cfg.delivery.clone()),
+            None => (vec![], NotifierDeliveryConfig::default()),
+        };
+
+        let sends = queue.take_due(Utc::now()).into_iter().map(|group| {
+            let target = targets.iter().find(|t| t.id == group[0].target);
+            let cfg = &cfg;
+            async move {
+                match target {
+                    Some(target) => self.deliver_to(target, group, cfg).await,
+                    None => {
+                        warn!(
+                            "Dropping {} notification(s) for removed target {}",
+                            group.len(),
+                            group[0].target
+                        );
+                        vec![]
+                    }
+                }
+            }
+        });
+        let retries = futures::future::join_all(sends).await;
+        queue.requeue(retries.into_iter().flatten());
+    }
+
+    /// Sends the deliveries to a single target, and returns the ones that
+    /// need to be retried later.
+    async fn deliver_to(
+        &self,
+        target: &Target,
+        group: Vec<Delivery>,
+        cfg: &NotifierDeliveryConfig,
+    ) -> Vec<Delivery> {
+        let name = target.notifier.name();
+        let mut group = group.into_iter();
+        while let Some(mut delivery) = group.next() {
+            let sent = target
+                .notifier
+                .deliver(&self.client, &delivery.id, &delivery.payload)
+                .await;
+            let e = match sent {
+                Ok(_) => {
+                    info!("Sent notification to {}", name);
+                    continue;
+                }
+                Err(e) => e,
+            };
+
+            // Rate limits don't count as failed attempts
+            let retry_in = match e.downcast_ref::<RateLimited>() {
+                Some(RateLimited(retry_after)) => {
+                    warn!("{} is rate limited, retrying in {:?}", name, retry_after);
+                    *retry_after
+                }
+                None => {
+                    delivery.attempts += 1;
+                    if delivery.attempts >= cfg.max_attempts {
+                        error!(
+                            "Failed to send notification to {}, giving up after {} attempt(s): {:?}",
+                            name, delivery.attempts, e
+                        );
+                        if let Payload::Task(notification) = &delivery.payload {
+                            if notification.status.is_final() {
+                                target.notifier.forget(&notification.task.video_id);
+                            }
+                        }
+                        continue;
+                    }
+                    let retry_in = backoff(cfg, delivery.attempts);
+                    warn!(
+                        "Failed to send notification to {}, retrying in {:?}: {:?}",
+                        name, retry_in, e
+                    );
+                    retry_in
+                }
+            };
+
+            // Hold back the rest of the notifications to keep them in order
+            let due = Utc::now()
+                + chrono::Duration::from_std(retry_in)
+                    .unwrap_or_else(|_| chrono::Duration::days(1));
+            delivery.due = due;
+            return std::iter::once(delivery)
+                .chain(group.map(|mut d| {
+                    d.due = d.due.max(due);
+                    d
+                }))
+                .collect();
+        }
+        vec![]
+    }
+}
+
+#[async_trait]
+impl Module for Dispatcher {
+    fn new(config: Arc<RwLock<Config>>) -> Self {
+        let client = network::module_client_builder(&config)
+            .timeout(SEND_TIMEOUT)
+            .build()
+            .expect("Failed to create client");
+        Self { config, client }
+    }
+
+    async fn run(&self, _tx: &BusTx<Message>, rx: &mut mpsc::Receiver<Message>) -> Result<()> {
+        let queue_file = match &self.config.read().await.notifier {
+            Some(cfg) => cfg.delivery.queue_file.clone(),
+            None => None,
+        };
+        let mut queue = Queue::load(queue_file.as_deref()).await?;
+        let mut progress = ProgressTimes::new();
+        let mut checked = HashMap::new();
+
+        loop {
+            // Wait for a notification, or until the next delivery is due
+            let wait = queue
+                .next_due()
+                .map(|due| (due - Utc::now()).to_std().unwrap_or(Duration::ZERO));
+            tokio::select! {
+                message = rx.recv() => match message {
+                    Some(Message::ToNotify(notification)) => {
+                        if notification.status.is_final() {
+                            let video_id = &notification.task.video_id;
+                            progress.retain(|(_, id), _| id != video_id);
+                            checked.remove(video_id);
+                        }
+                        self.enqueue(&mut queue, notification, None).await;
+                    }
+                    Some(Message::RecordingStatus(rs)) if *rs.status.state() == YTAState::Recording => {
+                        if !progress_due(&mut checked, &rs.task.video_id, Instant::now()) {
+                            continue;
+                        }
+                        let notification = Notification {
+                            task: rs.task,
+                            status: TaskStatus::Recording,
+                            recording: Some(rs.status),
+                            output_file: None,
+                            output_size: None,
+                        };
+                        if self.enqueue(&mut queue, notification, Some(&mut progress)).await == 0 {
+                            continue;
+                        }
+                    }
+                    Some(Message::SystemEvent(event)) => {
+                        if self.enqueue_event(&mut queue, event).await == 0 {
+                            continue;
+                        }
+                    }
+                    Some(_) => continue,
+                    None => break,
+                },
+                _ = tokio::time::sleep(wait.unwrap_or_default()), if wait.is_some() => {}
+            }
+
+            self.deliver(&mut queue).await;
+            if let Err(e) = queue.save().await {
+                error!("{:?}", e);
+            }
+        }
+
+        if !queue.is_empty() && queue_file.is_none() {
+            warn!("Dropping undelivered notifications on shutdown");
+        }
+        debug!("Notifier module finished");
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        backoff, parse_retry_after, progress_due, without_url, Delivery, Notifier, Payload,
+        RateLimited, Target,
+    };
+    use crate::config::{Config, NotifierDeliveryConfig};
+    use crate::module::{Notification, SystemEvent, SystemEventKind, Task, TaskStatus};
+    use anyhow::Result;
+    use async_trait::async_trait;
+    use reqwest::Client;
+    use std::{
+        collections::HashMap,
+        time::{Duration, Instant},
+    };
+
+    struct Stub;
+
+    #[async_trait]
+    impl Notifier for Stub {
+        fn name(&self) -> String {
+            "stub".into()
+        }
+
+        fn notify_on(&self) -> &[TaskStatus] {
+            &[TaskStatus::Recording, TaskStatus::Done]
+        }
+
+        async fn send(&self, _: &Client, _: &Notification) -> Result<()> {
+            Ok(())
+        }
+    }
+
+    fn target(name: Option<&str>) -> Target {
+        Target {
+            id: "stub#0".into(),
+            name: name.map(String::from),
+            events: vec![],
+            notifier: Box::new(Stub),
+        }
+    }
+
+    fn notification(channel_id: &str, source: Option<&str>, status: TaskStatus) -> Notification {
+        Notification {
+            task: Task {
+                title: "Collab".into(),
+                video_id: "abc".into(),
+                video_picture: "".into(),
+                channel_name: "Host".into(),
+                channel_id: channel_id.into(),
+                channel_picture: None,
+                output_directory: "".into(),
+                scheduled_start: None,
+                recorder: Default::default(),
+                source_channel_id: source.map(String::from),
+            },
+            status,
+            recording: None,
+            output_file: None,
+            output_size: None,
+        }
+    }
+
+    #[test]
+    fn test_routing() {
+        let config: Config = toml::from_str(&format!(
+            "{}{}",
+            include_str!("../../../config.example.toml"),
+            r#"
+            [[channel]]
+            id = "UCaaaaaaaaaaaaaaaaaaaaaa"
+            name = "Host"
+            outpath = "./videos/host"
+            notifiers = ["host"]
+
+            [[channel]]
+            id = "UCbbbbbbbbbbbbbbbbbbbbbb"
+            name = "Talent"
+            outpath = "./videos/talent"
+            notifiers = ["talent"]
+            notify_on = ["done"]
+            "#
+        ))
+        .unwrap();
+        let wants = |target: &Target, notification: &Notification| {
+            target.wants(
+                notification,
+                config.channel(notification.task.source_channel()),
+            )
+        };
+        let (host, talent, unnamed) = (target(Some("host")), target(Some("talent")), target(None));
+
+        // Videos of a channel go to its notifiers
+        let n = notification("UCaaaaaaaaaaaaaaaaaaaaaa", None, TaskStatus::Recording);
+        assert!(wants(&host, &n));
+        assert!(!wants(&talent, &n));
+        assert!(!wants(&unnamed, &n));
+
+        // Collabs go to the notifiers of the mentioned channel, on its statuses
+        let n = notification(
+            "UCaaaaaaaaaaaaaaaaaaaaaa",
+            Some("UCbbbbbbbbbbbbbbbbbbbbbb"),
+            TaskStatus::Recording,
+        );
+        assert!(!wants(&host, &n));
+        assert!(!wants(&talent, &n));
+        let n = notification(
+            "UCaaaaaaaaaaaaaaaaaaaaaa",
+            Some("UCbbbbbbbbbbbbbbbbbbbbbb"),
+            TaskStatus::Done,
+        );
+        assert!(!wants(&host, &n));
+        assert!(wants(&talent, &n));
+
+        // Channels without notifiers, and unknown channels, go to unnamed
+        // targets only
+        for channel_id in ["UCP0BspO_AMEe3aQqqpo89Dg", "UCcccccccccccccccccccccc"] {
+            let n = notification(channel_id, None, TaskStatus::Done);
+            assert!(wants(&unnamed, &n));
+            assert!(!wants(&host, &n));
+        }
+        let n = notification("UCaaaaaaaaaaaaaaaaaaaaaa", None, TaskStatus::Failed);
+        assert!(!wants(&host, &n));
+    }
+
+    #[test]
+    fn test_parse_retry_after() {
+        assert_eq!(parse_retry_after("5"), Some(Duration::from_secs(5)));
+        assert_eq!(parse_retry_after("0.25"), Some(Duration::from_millis(250)));
+        assert_eq!(
+            parse_retry_after("Wed, 21 Oct 2015 07:28:00 GMT"),
+            Some(Duration::ZERO)
+        );
+        assert_eq!(parse_retry_after("-1"), None);
+        assert_eq!(parse_retry_after("soon"), None);
+    }
+
+    #[test]
+    fn test_progress_due() {
+        let mut checked = HashMap::new();
+        let start = Instant::now();
+        let at = |secs| start + Duration::from_secs(secs);
+
+        assert!(progress_due(&mut checked, "v1", at(0)));
+        assert!(!progress_due(&mut checked, "v1", at(1)));
+        assert!(progress_due(&mut checked, "v2", at(1)));
+        assert!(progress_due(&mut checked, "v1", at(5)));
+        assert!(!progress_due(&mut checked, "v1", at(9)));
+        assert!(progress_due(&mut checked, "v1", at(10)));
+    }
+
+    #[tokio::test]
+    async fn test_without_url() {
+        // Nothing listens on port 1, so this fails with the URL in the error
+        let e = Client::new()
+            .get("http://127.0.0.1:1/botsecret/sendMessage")
+            .send()
+            .await
+            .unwrap_err();
+        assert!(format!("{:?}", e).contains("botsecret"));
+        assert!(!format!("{:?}", without_url(e.into())).contains("botsecret"));
+
+        // Rate limits are kept, so they can still be recognized
+        let e = without_url(RateLimited(Duration::from_secs(5)).into());
+        assert!(e.downcast_ref::<RateLimited>().is_some());
+    }
+
+    #[test]
+    fn test_backoff() {
+        let cfg = NotifierDeliveryConfig::default();
+        assert_eq!(backoff(&cfg, 1), Duration::from_secs(5));
+        assert_eq!(backoff(&cfg, 3), Duration::from_secs(20));
+        assert_eq!(backoff(&cfg, 100), Duration::from_secs(600));
+    }
+
+    #[test]
+    fn test_delivery_id() {
+        let event = SystemEvent {
+            kind: SystemEventKind::Started,
+            message: "hi".into(),
+            timestamp: chrono::Utc::now(),
+        };
+        let first = Delivery::new("a".into(), Payload::Event(event.clone()));
+        let second = Delivery::new("a".into(), Payload::Event(event));
+        assert_ne!(first.id, second.id);
+
+        // The ID is kept when the queue is saved and loaded again
+        let mut json = serde_json::to_value(&first).unwrap();
+        let loaded: Delivery = serde_json::from_value(json.clone()).unwrap();
+        assert_eq!(loaded.id, first.id);
+
+        // Queues saved before deliveries had IDs get new ones
+        json.as_object_mut().unwrap().remove("id");
+        let loaded: Delivery = serde_json::from_value(json).unwrap();
+        assert!(!loaded.id.is_empty());
+        assert_ne!(loaded.id, first.id);
+    }
+}
@@ -0,0 +1,73 @@
+use super::template::{self, Defaults};
+use super::{video_url, Notification, Notifier, ResponseExt, TaskStatus};
+use crate::config::NotifierNtfyConfig;
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use reqwest::Client;
+use serde::Serialize;
+
+/// Publishes notifications to an ntfy topic.
+pub struct Ntfy(pub NotifierNtfyConfig);
+
+const DEFAULTS: Defaults = Defaults {
+    title: "{{status_title}}",
+    body: "{{task.title}}\n{{task.channel_name}}",
+};
+
+#[derive(Serialize)]
+struct Publish<'a> {
+    topic: &'a str,
+    title: String,
+    message: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    click: Option<String>,
+}
+
+#[async_trait]
+impl Notifier for Ntfy {
+    fn name(&self) -> String {
+        format!("ntfy topic {}", self.0.topic)
+    }
+
+    fn notify_on(&self) -> &[TaskStatus] {
+        &self.0.notify_on
+    }
+
+    async fn send(&self, client: &Client, notification: &Notification) -> Result<()> {
+        let rendered = template::render(self.0.template.as_ref(), &DEFAULTS, None, notification)?;
+        let message = Publish {
+            topic: &self.0.topic,
+            title: rendered.title,
+            message: rendered.body,
+            click: Some(video_url(notification)),
+        };
+        self.post(client, &message).await
+    }
+
+    async fn send_message(&self, client: &Client, title: &str, body: &str) -> Result<()> {
+        let message = Publish {
+            topic: &self.0.topic,
+            title: title.into(),
+            message: body.into(),
+            click: None,
+        };
+        self.post(client, &message).await
+    }
+}
+
+impl Ntfy {
+    async fn post(&self, client: &Client, message: &Publish<'_>) -> Result<()> {
+        // Publishing as JSON allows non-ASCII titles, unlike the headers
+        let mut req = client.post(&self.0.server).json(message);
+        if let Some(token) = &self.0.token {
+            req = req.bearer_auth(token);
+        }
+        req.send()
+            .await
+            .context("Failed to publish to ntfy")?
+            .check()
+            .await
+            .context("ntfy returned error")?;
+        Ok(())
+    }
+}
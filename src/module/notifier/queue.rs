@@ -0,0 +1,145 @@
+use super::{Notification, SystemEvent};
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::{
+    path::PathBuf,
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+/// Notifications waiting to be delivered, optionally kept in a file so they
+/// survive restarts.
+#[derive(Debug, Default)]
+pub struct Queue {
+    path: Option<PathBuf>,
+    deliveries: Vec<Delivery>,
+}
+
+/// A notification to be sent to a single target.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Delivery {
+    /// Identifies the delivery, and stays the same when it is retried.
+    #[serde(default = "delivery_id")]
+    pub id: String,
+    /// ID of the target, see `super::Target::id`.
+    pub target: String,
+    pub payload: Payload,
+    /// Number of failed attempts so far.
+    pub attempts: u32,
+    pub queued_at: DateTime<Utc>,
+    /// When to attempt the delivery next.
+    pub due: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Payload {
+    Task(Box<Notification>),
+    Event(SystemEvent),
+}
+
+/// Returns a delivery ID that is unique for this process, and unlikely to
+/// repeat after a restart.
+pub(super) fn delivery_id() -> String {
+    static COUNTER: AtomicU64 = AtomicU64::new(0);
+    format!(
+        "hoshinova-{}-{}",
+        Utc::now().timestamp_millis(),
+        COUNTER.fetch_add(1, Ordering::Relaxed)
+    )
+}
+
+impl Delivery {
+    pub fn new(target: String, payload: Payload) -> Self {
+        let now = Utc::now();
+        Self {
+            id: delivery_id(),
+            target,
+            payload,
+            attempts: 0,
+            queued_at: now,
+            due: now,
+        }
+    }
+}
+
+impl Queue {
+    /// Loads the queue from the given file, if any. A missing file is treated
+    /// as an empty queue.
+    pub async fn load(path: Option<&str>) -> Result<Self> {
+        let path = match path {
+            Some(path) => PathBuf::from(path),
+            None => return Ok(Self::default()),
+        };
+
+        let deliveries = match tokio::fs::read(&path).await {
+            Ok(bytes) => serde_json::from_slice(&bytes)
+                .with_context(|| format!("Failed to parse notification queue {:?}", path))?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => vec![],
+            Err(e) => {
+                return Err(e)
+                    .with_context(|| format!("Failed to read notification queue {:?}", path))
+            }
+        };
+
+        Ok(Self {
+            path: Some(path),
+            deliveries,
+        })
+    }
+
+    /// Writes the queue to its file, if one was configured.
+    pub async fn save(&self) -> Result<()> {
+        let path = match &self.path {
+            Some(path) => path,
+            None => return Ok(()),
+        };
+
+        let json = serde_json::to_vec_pretty(&self.deliveries)
+            .context("Failed to serialize notification queue")?;
+        let tmp = path.with_extension("tmp");
+        tokio::fs::write(&tmp, json)
+            .await
+            .with_context(|| format!("Failed to write notification queue {:?}", tmp))?;
+        tokio::fs::rename(&tmp, path)
+            .await
+            .with_context(|| format!("Failed to replace notification queue {:?}", path))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.deliveries.is_empty()
+    }
+
+    pub fn push(&mut self, delivery: Delivery) {
+        self.deliveries.push(delivery);
+    }
+
+    /// Returns when the next delivery is due.
+    pub fn next_due(&self) -> Option<DateTime<Utc>> {
+        self.deliveries.iter().map(|d| d.due).min()
+    }
+
+    /// Removes the deliveries that are due, grouped by target in the order
+    /// they were queued.
+    pub fn take_due(&mut self, now: DateTime<Utc>) -> Vec<Vec<Delivery>> {
+        let (due, rest) = std::mem::take(&mut self.deliveries)
+            .into_iter()
+            .partition::<Vec<_>, _>(|d| d.due <= now);
+        self.deliveries = rest;
+
+        let mut groups: Vec<Vec<Delivery>> = vec![];
+        for delivery in due {
+            match groups.iter_mut().find(|g| g[0].target == delivery.target) {
+                Some(group) => group.push(delivery),
+                None => groups.push(vec![delivery]),
+            }
+        }
+        groups
+    }
+
+    /// Puts deliveries that still need to be sent back into the queue.
+    pub fn requeue(&mut self, deliveries: impl IntoIterator<Item = Delivery>) {
+        self.deliveries.extend(deliveries);
+        self.deliveries.sort_by_key(|d| d.queued_at);
+    }
+}
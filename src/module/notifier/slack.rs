@@ -0,0 +1,71 @@
+use super::template::{self, Defaults};
+use super::{Notification, Notifier, ResponseExt, TaskStatus};
+use crate::config::NotifierSlackConfig;
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use reqwest::Client;
+use serde::Serialize;
+
+/// Sends notifications through a Slack incoming webhook.
+pub struct Slack(pub NotifierSlackConfig);
+
+const DEFAULTS: Defaults = Defaults {
+    title: "{{status_title}}",
+    body: "<{{video_url}}|{{task.title}}>\n{{task.channel_name}}",
+};
+
+#[derive(Serialize)]
+struct SlackMessage {
+    text: String,
+}
+
+/// Escapes the characters that have a special meaning in Slack messages.
+fn escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+#[async_trait]
+impl Notifier for Slack {
+    fn name(&self) -> String {
+        "Slack webhook".into()
+    }
+
+    fn notify_on(&self) -> &[TaskStatus] {
+        &self.0.notify_on
+    }
+
+    async fn send(&self, client: &Client, notification: &Notification) -> Result<()> {
+        let rendered = template::render(
+            self.0.template.as_ref(),
+            &DEFAULTS,
+            Some(escape),
+            notification,
+        )?;
+        self.post(client, &rendered.title, &rendered.body).await
+    }
+
+    async fn send_message(&self, client: &Client, title: &str, body: &str) -> Result<()> {
+        self.post(client, &escape(title), &escape(body)).await
+    }
+}
+
+impl Slack {
+    async fn post(&self, client: &Client, title: &str, body: &str) -> Result<()> {
+        let message = SlackMessage {
+            text: format!("*{}*: {}", title, body),
+        };
+
+        client
+            .post(&self.0.webhook_url)
+            .json(&message)
+            .send()
+            .await
+            .context("Failed to send Slack webhook")?
+            .check()
+            .await
+            .context("Slack webhook returned error")?;
+        Ok(())
+    }
+}
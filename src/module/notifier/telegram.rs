@@ -0,0 +1,82 @@
+use super::template::{self, Defaults};
+use super::{without_url, Notification, Notifier, ResponseExt, TaskStatus};
+use crate::config::NotifierTelegramConfig;
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use reqwest::Client;
+use serde::Serialize;
+
+/// Sends notifications to a chat through the Telegram Bot API.
+pub struct Telegram(pub NotifierTelegramConfig);
+
+const DEFAULTS: Defaults = Defaults {
+    title: "{{status_title}}",
+    body: "<a href=\"{{video_url}}\">{{task.title}}</a>\n{{task.channel_name}}",
+};
+
+#[derive(Serialize)]
+struct SendMessage<'a> {
+    chat_id: &'a str,
+    text: String,
+    parse_mode: &'a str,
+}
+
+fn escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+#[async_trait]
+impl Notifier for Telegram {
+    fn name(&self) -> String {
+        format!("Telegram chat {}", self.0.chat_id)
+    }
+
+    fn notify_on(&self) -> &[TaskStatus] {
+        &self.0.notify_on
+    }
+
+    async fn send(&self, client: &Client, notification: &Notification) -> Result<()> {
+        let rendered = template::render(
+            self.0.template.as_ref(),
+            &DEFAULTS,
+            Some(escape),
+            notification,
+        )?;
+        self.post(client, &rendered.title, &rendered.body).await
+    }
+
+    async fn send_message(&self, client: &Client, title: &str, body: &str) -> Result<()> {
+        self.post(client, &escape(title), &escape(body)).await
+    }
+}
+
+impl Telegram {
+    async fn post(&self, client: &Client, title: &str, body: &str) -> Result<()> {
+        let message = SendMessage {
+            chat_id: &self.0.chat_id,
+            text: format!("<b>{}</b>\n{}", title, body),
+            parse_mode: "HTML",
+        };
+
+        // The URL contains the bot token, so keep it out of the errors
+        let url = format!(
+            "https://api.telegram.org/bot{}/sendMessage",
+            self.0.bot_token
+        );
+        client
+            .post(&url)
+            .json(&message)
+            .send()
+            .await
+            .map_err(|e| e.without_url())
+            .context("Failed to send Telegram message")?
+            .check()
+            .await
+            .map_err(without_url)
+            .context("Telegram returned error")?;
+        Ok(())
+    }
+}
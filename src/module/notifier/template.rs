@@ -0,0 +1,134 @@
+use super::{channel_url, status_title, video_url, Notification};
+use crate::config::NotificationTemplate;
+use anyhow::{Context, Result};
+use handlebars::Handlebars;
+use serde_json::json;
+
+/// The default templates of a notifier, used for any part the user didn't
+/// configure.
+pub struct Defaults {
+    pub title: &'static str,
+    pub body: &'static str,
+}
+
+/// A notification rendered from templates.
+pub struct Rendered {
+    pub title: String,
+    pub body: String,
+    pub content: Option<String>,
+}
+
+/// Renders the notification using the configured templates, falling back to
+/// the defaults. Values inserted into the templates are passed through
+/// `escape`, if given, to match the markup of the target.
+pub fn render(
+    template: Option<&NotificationTemplate>,
+    defaults: &Defaults,
+    escape: Option<fn(&str) -> String>,
+    notification: &Notification,
+) -> Result<Rendered> {
+    let mut hb = Handlebars::new();
+    match escape {
+        Some(escape) => hb.register_escape_fn(escape),
+        None => hb.register_escape_fn(handlebars::no_escape),
+    }
+    let ctx = context(notification);
+
+    let render = |source: &str| {
+        hb.render_template(source, &ctx)
+            .with_context(|| format!("Failed to render template {:?}", source))
+    };
+    Ok(Rendered {
+        title: render(
+            template
+                .and_then(|t| t.title.as_deref())
+                .unwrap_or(defaults.title),
+        )?,
+        body: render(
+            template
+                .and_then(|t| t.body.as_deref())
+                .unwrap_or(defaults.body),
+        )?,
+        content: match template.and_then(|t| t.content.as_deref()) {
+            Some(content) => Some(render(content)?),
+            None => None,
+        },
+    })
+}
+
+/// Builds the values available to templates.
+fn context(notification: &Notification) -> serde_json::Value {
+    json!({
+        "task": notification.task,
+        "status": notification.status,
+        "status_title": status_title(&notification.status),
+        "recording": notification.recording,
+        "output_file": notification.output_file,
+        "output_size": notification.output_size,
+        "output_size_human": notification.output_size.map(human_size),
+        "video_url": video_url(notification),
+        "channel_url": channel_url(notification),
+        "timestamp": chrono::Utc::now().to_rfc3339(),
+    })
+}
+
+pub fn human_size(bytes: u64) -> String {
+    const UNITS: [&str; 5] = ["B", "KiB", "MiB", "GiB", "TiB"];
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    format!("{:.2} {}", size, UNITS[unit])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{render, Defaults};
+    use crate::config::NotificationTemplate;
+    use crate::module::{Notification, Task, TaskStatus};
+
+    #[test]
+    fn test_render_template() {
+        let notification = Notification {
+            task: Task {
+                title: "<Karaoke>".into(),
+                video_id: "abc".into(),
+                video_picture: "".into(),
+                channel_name: "Moona".into(),
+                channel_id: "UC".into(),
+                channel_picture: None,
+                output_directory: "".into(),
+                scheduled_start: None,
+                recorder: Default::default(),
+                source_channel_id: None,
+            },
+            status: TaskStatus::Done,
+            recording: None,
+            output_file: Some("/videos/a.mp4".into()),
+            output_size: Some(3 * 1024 * 1024),
+        };
+        let defaults = Defaults {
+            title: "{{status_title}}",
+            body: "{{task.title}}",
+        };
+        let template = NotificationTemplate {
+            title: Some(r#"{{#if (eq status "done")}}録画完了{{else}}{{status}}{{/if}}"#.into()),
+            body: None,
+            content: Some("<@&123> {{output_size_human}}".into()),
+            colors: Default::default(),
+        };
+
+        let r = render(None, &defaults, None, &notification).unwrap();
+        assert_eq!(r.title, "Done");
+        assert_eq!(r.body, "<Karaoke>");
+        assert_eq!(r.content, None);
+
+        let escape = Some(handlebars::html_escape as fn(&str) -> String);
+        let r = render(Some(&template), &defaults, escape, &notification).unwrap();
+        assert_eq!(r.title, "録画完了");
+        assert_eq!(r.body, "&lt;Karaoke&gt;");
+        assert_eq!(r.content.as_deref(), Some("<@&123> 3.00 MiB"));
+    }
+}
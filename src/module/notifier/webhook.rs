@@ -0,0 +1,95 @@
+use super::template::{self, Defaults};
+use super::{Notification, Notifier, ResponseExt, SystemEvent, SystemEventKind, TaskStatus};
+use crate::{
+    config::NotifierWebhookConfig,
+    module::{recorder::YTAStatus, Task},
+    APP_NAME,
+};
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use reqwest::Client;
+use serde::Serialize;
+
+/// Posts the notification as JSON to an arbitrary URL.
+pub struct Webhook(pub NotifierWebhookConfig);
+
+const DEFAULTS: Defaults = Defaults {
+    title: "{{status_title}}",
+    body: "{{task.title}}",
+};
+
+#[derive(Serialize)]
+struct WebhookPayload<'a> {
+    task: &'a Task,
+    status: &'a TaskStatus,
+    title: String,
+    body: String,
+    recording: &'a Option<YTAStatus>,
+    output_file: &'a Option<String>,
+    output_size: Option<u64>,
+    timestamp: String,
+    app: &'a str,
+}
+
+#[derive(Serialize)]
+struct EventPayload<'a> {
+    event: SystemEventKind,
+    title: &'a str,
+    body: &'a str,
+    timestamp: String,
+    app: &'a str,
+}
+
+#[async_trait]
+impl Notifier for Webhook {
+    fn name(&self) -> String {
+        format!("webhook {}", self.0.url)
+    }
+
+    fn notify_on(&self) -> &[TaskStatus] {
+        &self.0.notify_on
+    }
+
+    async fn send(&self, client: &Client, notification: &Notification) -> Result<()> {
+        let rendered = template::render(self.0.template.as_ref(), &DEFAULTS, None, notification)?;
+        let payload = WebhookPayload {
+            task: &notification.task,
+            status: &notification.status,
+            title: rendered.title,
+            body: rendered.body,
+            recording: &notification.recording,
+            output_file: &notification.output_file,
+            output_size: notification.output_size,
+            timestamp: chrono::Utc::now().to_rfc3339(),
+            app: APP_NAME,
+        };
+        self.post(client, &payload).await
+    }
+
+    async fn send_event(&self, client: &Client, event: &SystemEvent) -> Result<()> {
+        let payload = EventPayload {
+            event: event.kind,
+            title: event.kind.title(),
+            body: &event.message,
+            timestamp: event.timestamp.to_rfc3339(),
+            app: APP_NAME,
+        };
+        self.post(client, &payload).await
+    }
+}
+
+impl Webhook {
+    async fn post(&self, client: &Client, payload: &(impl Serialize + Sync)) -> Result<()> {
+        let mut req = client.post(&self.0.url).json(payload);
+        for (name, value) in &self.0.headers {
+            req = req.header(name, value);
+        }
+        req.send()
+            .await
+            .context("Failed to send webhook")?
+            .check()
+            .await
+            .context("Webhook returned error")?;
+        Ok(())
+    }
+}
@@ -0,0 +1,480 @@
+use super::{
+    Archived, Message, Module, PostProcessState, PostProcessStatus, Relocated, SystemEvent,
+    SystemEventKind, Task,
+};
+use crate::{
+    config::{ChannelConfig, Config, PostProcessConfig},
+    msgbus::BusTx,
+    youtube,
+};
+use anyhow::{anyhow, bail, Context, Result};
+use async_trait::async_trait;
+use handlebars::Handlebars;
+use serde_json::json;
+use sha2::{Digest, Sha256};
+use std::{
+    io::Read,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+use tokio::{
+    process::Command,
+    sync::{mpsc, RwLock},
+};
+
+/// Runs the configured post-processing steps on finished recordings, one
+/// after another, and reports their progress on the bus.
+pub struct PostProcessor {
+    config: Arc<RwLock<Config>>,
+}
+
+/// A recording being post-processed, passed from one step to the next.
+struct Job {
+    task: Task,
+    /// The video file, which can change e.g. when it is remuxed.
+    video: PathBuf,
+    /// The video and every file written next to it so far.
+    files: Vec<PathBuf>,
+    /// Files renamed or removed by the current step, to be reported so the
+    /// history of recordings stays accurate.
+    relocated: Vec<(PathBuf, Option<PathBuf>)>,
+}
+
+/// Returns whether the step should run for a channel, mirroring how
+/// notification targets are selected.
+fn wants(step: &PostProcessConfig, channel: Option<&ChannelConfig>) -> bool {
+    match (channel.and_then(|c| c.postprocess.as_ref()), step.name()) {
+        (Some(names), Some(name)) => names.iter().any(|n| n == name),
+        (Some(_), None) => false,
+        (None, name) => name.is_none(),
+    }
+}
+
+impl Job {
+    /// Adds a file written next to the video, unless it is already known.
+    fn add(&mut self, path: PathBuf) {
+        if !self.files.contains(&path) {
+            self.files.push(path);
+        }
+    }
+
+    /// Forgets a file that was renamed or removed.
+    fn relocate(&mut self, from: PathBuf, to: Option<PathBuf>) {
+        self.files.retain(|f| *f != from);
+        if let Some(to) = &to {
+            self.add(to.clone());
+        }
+        self.relocated.push((from, to));
+    }
+
+    /// Returns the path of a file next to the video with another extension.
+    fn sidecar(&self, ext: &str) -> PathBuf {
+        self.video.with_extension(ext)
+    }
+
+    /// Renders a template with the details of the job.
+    fn render(&self, template: &str) -> Result<String> {
+        let mut hb = Handlebars::new();
+        hb.register_escape_fn(handlebars::no_escape);
+        let file_name = |p: &Path| {
+            p.file_name()
+                .map(|s| s.to_string_lossy().into_owned())
+                .unwrap_or_default()
+        };
+        let ctx = json!({
+            "task": self.task,
+            "file": self.video,
+            "filename": file_name(&self.video),
+            "directory": self.video.parent(),
+            "files": self.files,
+        });
+        hb.render_template(template, &ctx)
+            .with_context(|| format!("Failed to render template {:?}", template))
+    }
+}
+
+impl PostProcessor {
+    /// Runs a single step, returning a short description of what it did.
+    async fn run_step(&self, step: &PostProcessConfig, job: &mut Job) -> Result<String> {
+        match step {
+            PostProcessConfig::Command { command, .. } => {
+                let args = command
+                    .iter()
+                    .map(|arg| job.render(arg))
+                    .collect::<Result<Vec<_>>>()?;
+                let (program, args) = args.split_first().ok_or(anyhow!("Command is empty"))?;
+                run(Command::new(program).args(args)).await?;
+                Ok(format!("Ran {}", program))
+            }
+            PostProcessConfig::Remux {
+                ffmpeg_path,
+                keep_original,
+                ..
+            } => {
+                let output = job.sidecar("mkv");
+                if output == job.video {
+                    return Ok("Already an mkv file".to_string());
+                }
+                run(Command::new(ffmpeg_path)
+                    .args(["-hide_banner", "-loglevel", "error", "-n", "-i"])
+                    .arg(&job.video)
+                    .args(["-map", "0", "-c", "copy"])
+                    .arg(&output))
+                .await?;
+                if *keep_original {
+                    job.add(output.clone());
+                } else {
+                    tokio::fs::remove_file(&job.video)
+                        .await
+                        .with_context(|| format!("Failed to remove {:?}", job.video))?;
+                    job.relocate(job.video.clone(), Some(output.clone()));
+                }
+                job.video = output;
+                Ok(format!("Remuxed to {}", job.video.display()))
+            }
+            PostProcessConfig::Checksum { .. } => {
+                let files = job.files.clone();
+                for file in files {
+                    let path = file.clone();
+                    let hash = tokio::task::spawn_blocking(move || sha256(&path)).await??;
+                    let name = file
+                        .file_name()
+                        .map(|s| s.to_string_lossy().into_owned())
+                        .unwrap_or_default();
+                    let mut checksum_file = file.clone().into_os_string();
+                    checksum_file.push(".sha256");
+                    let checksum_file = PathBuf::from(checksum_file);
+                    tokio::fs::write(&checksum_file, format!("{}  {}\n", hash, name))
+                        .await
+                        .with_context(|| format!("Failed to write {:?}", checksum_file))?;
+                    job.add(checksum_file);
+                }
+                Ok("Wrote checksums".to_string())
+            }
+            PostProcessConfig::Sidecar {
+                thumbnail,
+                description,
+                ..
+            } => {
+                let mut written = vec![];
+                // The thumbnail may have been saved already with
+                // `ytarchive.save_thumbnail`
+                let path = job.sidecar("jpg");
+                if *thumbnail && !job.files.contains(&path) {
+                    let bytes = youtube::video::fetch_thumbnail(
+                        youtube::shared(),
+                        &job.task.video_id,
+                        &job.task.video_picture,
+                    )
+                    .await?;
+                    tokio::fs::write(&path, bytes)
+                        .await
+                        .with_context(|| format!("Failed to write {:?}", path))?;
+                    job.add(path);
+                    written.push("thumbnail");
+                }
+                if *description {
+                    let path = job.sidecar("description");
+                    let url = format!("https://www.youtube.com/watch?v={}", job.task.video_id);
+                    let ipr =
+                        youtube::video::fetch_initial_player_response(youtube::shared(), &url)
+                            .await?;
+                    tokio::fs::write(&path, ipr.video_details.short_description)
+                        .await
+                        .with_context(|| format!("Failed to write {:?}", path))?;
+                    job.add(path);
+                    written.push("description");
+                }
+                if written.is_empty() {
+                    return Ok("Nothing to write".to_string());
+                }
+                Ok(format!("Wrote {}", written.join(" and ")))
+            }
+            PostProcessConfig::Rclone {
+                remote,
+                rclone_path,
+                delete_after,
+                ..
+            } => {
+                let remote = job.render(remote)?;
+                let subcommand = if *delete_after { "move" } else { "copy" };
+                for file in job.files.clone() {
+                    run(Command::new(rclone_path)
+                        .arg(subcommand)
+                        .arg(&file)
+                        .arg(&remote))
+                    .await?;
+                    if *delete_after {
+                        job.relocate(file, None);
+                    }
+                }
+                Ok(format!("Uploaded to {}", remote))
+            }
+        }
+    }
+
+    /// Runs every step that applies to the recording, stopping at the first
+    /// one that fails.
+    async fn process(&self, archived: Archived, tx: &BusTx<Message>) {
+        let steps = {
+            let config = self.config.read().await;
+            let channel = config.channel(archived.task.source_channel());
+            config
+                .postprocess
+                .iter()
+                .filter(|step| wants(step, channel))
+                .cloned()
+                .collect::<Vec<_>>()
+        };
+
+        let mut job = Job {
+            task: archived.task,
+            video: PathBuf::from(&archived.output_file),
+            files: std::iter::once(archived.output_file)
+                .chain(archived.sidecars)
+                .map(PathBuf::from)
+                .collect(),
+            relocated: vec![],
+        };
+        for step in steps {
+            let _ = tx
+                .send(status(&job.task, &step, PostProcessState::Running, None))
+                .await;
+            debug!("Running {} on {}", step.describe(), job.video.display());
+
+            let result = self.run_step(&step, &mut job).await;
+            for (from, to) in job.relocated.drain(..) {
+                let relocated = Relocated {
+                    video_id: job.task.video_id.clone(),
+                    from: from.to_string_lossy().into_owned(),
+                    to: to.map(|to| to.to_string_lossy().into_owned()),
+                };
+                let _ = tx.send(Message::Relocated(relocated)).await;
+            }
+
+            match result {
+                Ok(message) => {
+                    info!("[{}] {}: {}", job.task.video_id, step.describe(), message);
+                    let _ = tx
+                        .send(status(
+                            &job.task,
+                            &step,
+                            PostProcessState::Done,
+                            Some(message),
+                        ))
+                        .await;
+                }
+                Err(e) => {
+                    error!(
+                        "[{}] {} failed: {:?}",
+                        job.task.video_id,
+                        step.describe(),
+                        e
+                    );
+                    let message = format!("{:#}", e);
+                    let event = SystemEvent::new(
+                        SystemEventKind::PostProcessFailed,
+                        format!(
+                            "{} failed for {}: {}",
+                            step.describe(),
+                            job.task.title,
+                            message
+                        ),
+                    );
+                    let _ = tx
+                        .send(status(
+                            &job.task,
+                            &step,
+                            PostProcessState::Failed,
+                            Some(message),
+                        ))
+                        .await;
+                    let _ = tx.send(Message::SystemEvent(event)).await;
+                    return;
+                }
+            }
+        }
+    }
+}
+
+fn status(
+    task: &Task,
+    step: &PostProcessConfig,
+    state: PostProcessState,
+    message: Option<String>,
+) -> Message {
+    Message::PostProcess(PostProcessStatus {
+        task: task.clone(),
+        step: step.describe().to_string(),
+        state,
+        message,
+        timestamp: chrono::Utc::now(),
+    })
+}
+
+/// Runs a command to completion, failing with its error output if it exits
+/// unsuccessfully.
+async fn run(command: &mut Command) -> Result<()> {
+    let output = command
+        .kill_on_drop(true)
+        .output()
+        .await
+        .with_context(|| format!("Failed to run {:?}", command))?;
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        bail!(
+            "{:?} exited with {}: {}",
+            command,
+            output.status,
+            stderr.trim()
+        );
+    }
+    Ok(())
+}
+
+/// Returns the SHA-256 checksum of a file as a hex string.
+fn sha256(path: &Path) -> Result<String> {
+    let mut file =
+        std::fs::File::open(path).with_context(|| format!("Failed to open {:?}", path))?;
+    let mut hasher = Sha256::new();
+    let mut buf = vec![0; 1 << 20];
+    loop {
+        let n = file
+            .read(&mut buf)
+            .with_context(|| format!("Failed to read {:?}", path))?;
+        if n == 0 {
+            break;
+        }
+        hasher.update(&buf[..n]);
+    }
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+#[async_trait]
+impl Module for PostProcessor {
+    fn new(config: Arc<RwLock<Config>>) -> Self {
+        Self { config }
+    }
+
+    async fn run(&self, tx: &BusTx<Message>, rx: &mut mpsc::Receiver<Message>) -> Result<()> {
+        // Recordings are processed one at a time in the background, so e.g.
+        // uploads don't compete for bandwidth or hold up shutting down
+        let (job_tx, mut job_rx) = mpsc::unbounded_channel();
+        let worker = Self {
+            config: self.config.clone(),
+        };
+        let tx = tx.clone();
+        tokio::spawn(async move {
+            while let Some(archived) = job_rx.recv().await {
+                worker.process(archived, &tx).await;
+            }
+        });
+
+        while let Some(msg) = rx.recv().await {
+            if let Message::Finalized(archived) = msg {
+                let _ = job_tx.send(archived);
+            }
+        }
+
+        debug!("Post-processing module finished");
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{wants, Job};
+    use crate::config::{ChannelConfig, PostProcessConfig};
+    use crate::module::Task;
+    use std::path::PathBuf;
+
+    fn channel(postprocess: Option<&str>) -> ChannelConfig {
+        let postprocess = postprocess
+            .map(|p| format!("postprocess = {}", p))
+            .unwrap_or_default();
+        toml::from_str(&format!(
+            r#"
+            id = "UCP0BspO_AMEe3aQqqpo89Dg"
+            name = "Moona Hoshinova"
+            filters = []
+            outpath = "./videos/moona"
+            {}
+            "#,
+            postprocess
+        ))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_wants() {
+        let step = |s: &str| -> PostProcessConfig { toml::from_str(s).unwrap() };
+        let unnamed = step(r#"type = "checksum""#);
+        let upload = step(
+            r#"
+            type = "rclone"
+            name = "upload"
+            remote = "gdrive:"
+            "#,
+        );
+
+        // Channels without a list only run the unnamed steps
+        for channel in [None, Some(&channel(None))] {
+            assert!(wants(&unnamed, channel));
+            assert!(!wants(&upload, channel));
+        }
+
+        // Channels with a list only run the named steps in it
+        let listed = channel(Some(r#"["upload"]"#));
+        assert!(!wants(&unnamed, Some(&listed)));
+        assert!(wants(&upload, Some(&listed)));
+        let empty = channel(Some("[]"));
+        assert!(!wants(&unnamed, Some(&empty)));
+        assert!(!wants(&upload, Some(&empty)));
+        let other = channel(Some(r#"["remux"]"#));
+        assert!(!wants(&upload, Some(&other)));
+    }
+
+    #[test]
+    fn test_render() {
+        let job = Job {
+            task: Task {
+                title: "Karaoke!".into(),
+                video_id: "abc".into(),
+                video_picture: "".into(),
+                channel_name: "Moona Hoshinova".into(),
+                channel_id: "UCP0BspO_AMEe3aQqqpo89Dg".into(),
+                channel_picture: None,
+                output_directory: "/videos/moona".into(),
+                scheduled_start: None,
+                recorder: Default::default(),
+                source_channel_id: None,
+            },
+            video: PathBuf::from("/videos/moona/Karaoke! [abc].mp4"),
+            files: vec![
+                PathBuf::from("/videos/moona/Karaoke! [abc].mp4"),
+                PathBuf::from("/videos/moona/Karaoke! [abc].jpg"),
+            ],
+            relocated: vec![],
+        };
+
+        assert_eq!(
+            job.render("{{task.channel_name}}/{{task.video_id}}")
+                .unwrap(),
+            "Moona Hoshinova/abc"
+        );
+        // Values aren't HTML-escaped
+        assert_eq!(
+            job.render("{{file}}").unwrap(),
+            "/videos/moona/Karaoke! [abc].mp4"
+        );
+        assert_eq!(
+            job.render("{{directory}} {{filename}}").unwrap(),
+            "/videos/moona Karaoke! [abc].mp4"
+        );
+        assert_eq!(
+            job.render("{{#each files}}{{this}};{{/each}}").unwrap(),
+            "/videos/moona/Karaoke! [abc].mp4;/videos/moona/Karaoke! [abc].jpg;"
+        );
+        assert!(job.render("{{#if}}").is_err());
+    }
+}
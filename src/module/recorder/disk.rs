@@ -0,0 +1,388 @@
+use crate::{
+    config::{Config, LowSpaceAction},
+    module::{Message, SystemEvent, SystemEventKind, Task},
+    msgbus::BusTx,
+};
+use anyhow::{bail, Context, Result};
+use bytesize::ByteSize;
+use serde::Serialize;
+use std::{
+    collections::HashMap,
+    path::Path,
+    time::{Duration, SystemTime},
+};
+use tokio::sync::RwLock;
+use ts_rs::TS;
+
+/// How often to check again while a recording is paused for lack of space.
+const RECHECK_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Files in the working directory that weren't modified for this long are
+/// assumed to be left behind by old recordings.
+const STALE_TEMP_AGE: Duration = Duration::from_secs(60 * 60);
+
+/// Extensions of the files ytarchive and yt-dlp download into before the
+/// final file is written.
+const TEMP_EXTENSIONS: &[&str] = &["ts", "frag", "part", "ytdl"];
+
+#[derive(Debug, Clone, TS, Serialize)]
+#[ts(export, export_to = "web/src/bindings/")]
+pub struct DiskUsage {
+    pub path: String,
+    pub total: u64,
+    pub available: u64,
+    /// Free space needed to start a recording, if configured.
+    pub min_free: Option<u64>,
+    pub low: bool,
+}
+
+/// Returns the free space of the volume the path is on. The path doesn't
+/// need to exist yet.
+pub fn available_space(path: &str) -> Result<u64> {
+    fs2::available_space(existing_ancestor(path))
+        .with_context(|| format!("Failed to get free space of {}", path))
+}
+
+/// Returns the path itself, or its closest ancestor that exists.
+fn existing_ancestor(path: &str) -> &Path {
+    let path = Path::new(path);
+    path.ancestors()
+        .find(|p| p.exists())
+        .filter(|p| !p.as_os_str().is_empty())
+        .unwrap_or_else(|| Path::new("."))
+}
+
+/// Returns the free space needed in the output directory.
+fn min_free_output_space(cfg: &Config, outpath: &str) -> Option<ByteSize> {
+    cfg.channel
+        .iter()
+        .find(|c| c.outpath == outpath)
+        .and_then(|c| c.min_free_space)
+        .or(cfg.ytarchive.min_free_output_space)
+}
+
+/// Reports the disk usage of the working directory and every output
+/// directory.
+pub fn usage(cfg: &Config) -> Vec<DiskUsage> {
+    cfg.directories()
+        .into_iter()
+        .filter_map(|dir| {
+            let path = existing_ancestor(dir);
+            let (total, available) = match (fs2::total_space(path), fs2::available_space(path)) {
+                (Ok(total), Ok(available)) => (total, available),
+                (Err(e), _) | (_, Err(e)) => {
+                    debug!("Failed to get disk usage of {}: {}", dir, e);
+                    return None;
+                }
+            };
+            let min_free = if dir == cfg.ytarchive.working_directory {
+                cfg.ytarchive.min_free_space
+            } else {
+                min_free_output_space(cfg, dir)
+            }
+            .map(|min| min.as_u64());
+            Some(DiskUsage {
+                path: dir.to_string(),
+                total,
+                available,
+                min_free,
+                low: min_free.map(|min| available < min).unwrap_or(false),
+            })
+        })
+        .collect()
+}
+
+/// Returns why the task can't be started, if a directory it needs is low on
+/// space.
+fn shortfall(cfg: &Config, task: &Task) -> Result<Option<String>> {
+    let dirs = [
+        (
+            &cfg.ytarchive.working_directory,
+            cfg.ytarchive.min_free_space,
+        ),
+        (
+            &task.output_directory,
+            min_free_output_space(cfg, &task.output_directory),
+        ),
+    ];
+    for (dir, min) in dirs {
+        let min = match min {
+            Some(min) => min,
+            None => continue,
+        };
+        let available = available_space(dir)?;
+        if available < min.as_u64() {
+            return Ok(Some(format!(
+                "only {} of {} free in {}",
+                ByteSize(available).to_string_as(true),
+                min.to_string_as(true),
+                dir
+            )));
+        }
+    }
+    Ok(None)
+}
+
+/// Waits until there is enough free space to record the task, or fails if
+/// the configuration says to refuse it instead. Files of the videos being
+/// recorded are never cleaned up to make room.
+pub async fn ensure_space(
+    cfg: &Config,
+    task: &Task,
+    active_ids: &RwLock<HashMap<String, Vec<String>>>,
+    bus: &BusTx<Message>,
+) -> Result<()> {
+    let mut paused = false;
+    loop {
+        let reason = match shortfall(cfg, task)? {
+            Some(reason) => reason,
+            None => break,
+        };
+
+        // Try to make room in the working directory first
+        if let (true, Some(min)) = (cfg.ytarchive.clean_temp_files, cfg.ytarchive.min_free_space) {
+            let dir = cfg.ytarchive.working_directory.clone();
+            let active = active_ids.read().await.keys().cloned().collect::<Vec<_>>();
+            let freed =
+                tokio::task::spawn_blocking(move || clean_temp_files(&dir, min.as_u64(), &active))
+                    .await??;
+            if freed > 0 {
+                continue;
+            }
+        }
+
+        if cfg.ytarchive.low_space_action == LowSpaceAction::Refuse {
+            bail!("Not enough disk space to record: {}", reason);
+        }
+        if !paused {
+            paused = true;
+            warn!("Pausing recording of {}: {}", task.video_id, reason);
+            let event = SystemEvent::new(
+                SystemEventKind::DiskSpaceLow,
+                format!("Paused recording of {}: {}", task.title, reason),
+            );
+            let _ = bus.send(Message::SystemEvent(event)).await;
+        }
+        tokio::time::sleep(RECHECK_INTERVAL).await;
+    }
+
+    if paused {
+        info!("Resuming recording of {}", task.video_id);
+    }
+    Ok(())
+}
+
+/// Deletes what ytarchive and yt-dlp left behind in the working directory
+/// while downloading, if it hasn't been modified for a while, oldest first,
+/// until `min_free` bytes are free. Anything else, such as finished
+/// recordings that couldn't be moved to a full output directory, is kept, as
+/// are the files of the given videos. Returns the number of entries deleted.
+fn clean_temp_files(dir: &str, min_free: u64, active_ids: &[String]) -> Result<usize> {
+    let now = SystemTime::now();
+    let mut entries = std::fs::read_dir(dir)
+        .with_context(|| format!("Failed to read working directory {}", dir))?
+        .filter_map(|entry| {
+            let entry = entry.ok()?;
+            let modified = entry.metadata().ok()?.modified().ok()?;
+            let age = now.duration_since(modified).unwrap_or_default();
+            (age >= STALE_TEMP_AGE).then(|| (modified, entry.path()))
+        })
+        .filter(|(_, path)| {
+            let name = path.file_name().unwrap_or_default().to_string_lossy();
+            !active_ids.iter().any(|id| name.contains(id.as_str())) && is_temp_artifact(path)
+        })
+        .collect::<Vec<_>>();
+    entries.sort();
+
+    let mut deleted = 0;
+    for (_, path) in entries {
+        if available_space(dir)? >= min_free {
+            break;
+        }
+        let res = if path.is_dir() {
+            std::fs::remove_dir_all(&path)
+        } else {
+            std::fs::remove_file(&path)
+        };
+        match res {
+            Ok(_) => {
+                warn!("Deleted old temporary file {}", path.display());
+                deleted += 1;
+            }
+            Err(e) => error!("Failed to delete {}: {}", path.display(), e),
+        }
+    }
+    Ok(deleted)
+}
+
+/// Returns whether the path is a file that a download was written to, or a
+/// directory of fragments holding nothing but such files.
+fn is_temp_artifact(path: &Path) -> bool {
+    if !path.is_dir() {
+        return is_temp_file(path);
+    }
+    let entries = match std::fs::read_dir(path) {
+        Ok(entries) => entries
+            .map(|e| e.map(|e| e.path()))
+            .collect::<Result<Vec<_>, _>>(),
+        Err(_) => return false,
+    };
+    match entries {
+        Ok(entries) => {
+            !entries.is_empty() && entries.iter().all(|p| p.is_file() && is_temp_file(p))
+        }
+        Err(_) => false,
+    }
+}
+
+fn is_temp_file(path: &Path) -> bool {
+    // yt-dlp names fragments `<file>.part-Frag<n>`
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    name.contains(".part-Frag")
+        || path
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .map(|ext| TEMP_EXTENSIONS.contains(&ext))
+            .unwrap_or(false)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{clean_temp_files, ensure_space, STALE_TEMP_AGE};
+    use crate::module::recorder::test_dir;
+    use crate::{
+        config::Config,
+        module::{Message, Task},
+        msgbus::MessageBus,
+    };
+    use std::{collections::HashMap, fs::File, path::Path, time::SystemTime};
+    use tokio::sync::RwLock;
+
+    /// Creates a file, or a directory of fragments, that was last modified
+    /// long enough ago to be cleaned up.
+    fn create_old(path: &Path, fragments: &[&str]) {
+        if fragments.is_empty() {
+            std::fs::write(path, "x").unwrap();
+        } else {
+            std::fs::create_dir(path).unwrap();
+            for fragment in fragments {
+                std::fs::write(path.join(fragment), "x").unwrap();
+            }
+        }
+        let old = SystemTime::now() - STALE_TEMP_AGE * 2;
+        File::open(path).unwrap().set_modified(old).unwrap();
+    }
+
+    #[test]
+    fn test_clean_temp_files() {
+        let dir = test_dir("clean");
+        let old = [
+            ("Karaoke (aaa).f299.ts", &[][..]),
+            ("Karaoke (aaa).f140.ts", &[]),
+            ("Zatsudan [bbb].mp4.part", &[]),
+            ("Zatsudan [bbb].f299.mp4.part-Frag12", &[]),
+            ("Zatsudan [bbb].mp4.ytdl", &[]),
+            ("Zatsudan (bbb)", &["frag1.frag", "frag2.frag"]),
+            // Recordings that couldn't be moved, chat logs and other files
+            ("Finished (ccc).mp4", &[]),
+            ("Finished (ccc).live_chat.json", &[]),
+            ("notes", &["todo.txt", "frag1.frag"]),
+            ("empty", &[]),
+            // Being recorded
+            ("Live (ddd).f299.ts", &[]),
+            ("Live (ddd)", &["frag1.frag"]),
+        ];
+        for (name, fragments) in old {
+            if name == "empty" {
+                std::fs::create_dir(dir.join(name)).unwrap();
+            } else {
+                create_old(&dir.join(name), fragments);
+            }
+        }
+        std::fs::write(dir.join("Recent (eee).f299.ts"), "x").unwrap();
+
+        let deleted =
+            clean_temp_files(dir.to_str().unwrap(), u64::MAX, &["ddd".to_string()]).unwrap();
+        assert_eq!(deleted, 6);
+        let mut left = std::fs::read_dir(&dir)
+            .unwrap()
+            .map(|e| e.unwrap().file_name().to_string_lossy().into_owned())
+            .collect::<Vec<_>>();
+        left.sort();
+        assert_eq!(
+            left,
+            [
+                "Finished (ccc).live_chat.json",
+                "Finished (ccc).mp4",
+                "Live (ddd)",
+                "Live (ddd).f299.ts",
+                "Recent (eee).f299.ts",
+                "empty",
+                "notes"
+            ]
+        );
+
+        // Nothing is deleted while there is enough space
+        create_old(&dir.join("Karaoke (aaa).f299.ts"), &[]);
+        assert_eq!(clean_temp_files(dir.to_str().unwrap(), 0, &[]).unwrap(), 0);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_ensure_space() {
+        let dir = test_dir("space");
+        create_old(&dir.join("Karaoke (aaa).f299.ts"), &[]);
+        create_old(&dir.join("Finished (ccc).mp4"), &[]);
+
+        let config = |min_free_space: &str| -> Config {
+            toml::from_str(
+                &include_str!("../../../config.example.toml")
+                    .replace(
+                        "working_directory = \"temp\"",
+                        &format!("working_directory = {:?}", dir.to_str().unwrap()),
+                    )
+                    .replace(
+                        "# min_free_space = \"20 GiB\"",
+                        &format!("min_free_space = {:?}", min_free_space),
+                    )
+                    .replace(
+                        "# low_space_action = \"pause\"",
+                        "low_space_action = \"refuse\"",
+                    )
+                    .replace("# clean_temp_files = false", "clean_temp_files = true"),
+            )
+            .unwrap()
+        };
+        let task = Task {
+            title: "Karaoke".into(),
+            video_id: "aaa".into(),
+            video_picture: "".into(),
+            channel_name: "Moona".into(),
+            channel_id: "UCP0BspO_AMEe3aQqqpo89Dg".into(),
+            channel_picture: None,
+            output_directory: dir.to_str().unwrap().into(),
+            scheduled_start: None,
+            recorder: Default::default(),
+            source_channel_id: None,
+        };
+        let mut bus = MessageBus::<Message>::new(10);
+        let tx = bus.add_tx();
+        let active_ids = RwLock::new(HashMap::new());
+
+        // There is enough space, so nothing is cleaned up
+        ensure_space(&config("1 B"), &task, &active_ids, &tx)
+            .await
+            .unwrap();
+        assert!(dir.join("Karaoke (aaa).f299.ts").exists());
+
+        // Cleaning up can't free enough, so the task is refused
+        assert!(ensure_space(&config("1000 PB"), &task, &active_ids, &tx)
+            .await
+            .is_err());
+        assert!(!dir.join("Karaoke (aaa).f299.ts").exists());
+        assert!(dir.join("Finished (ccc).mp4").exists());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,232 @@
+use crate::config::DuplicateMode;
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+};
+use ts_rs::TS;
+
+/// Remembers which videos have been recorded, and where the files went.
+#[derive(Debug, Default)]
+pub struct History {
+    path: Option<PathBuf>,
+    completed: HashMap<String, CompletedRecording>,
+}
+
+#[derive(Debug, Clone, TS, Serialize, Deserialize)]
+#[ts(export, export_to = "web/src/bindings/")]
+pub struct CompletedRecording {
+    pub video_id: String,
+    pub title: String,
+    pub channel_name: String,
+    pub finished_at: DateTime<Utc>,
+    /// Every copy of the recording, one per output directory.
+    pub output_files: Vec<String>,
+}
+
+impl History {
+    /// Loads the history from the given file, if any. A missing file is
+    /// treated as an empty history.
+    pub async fn load(path: Option<&str>) -> Result<Self> {
+        let path = match path {
+            Some(path) => PathBuf::from(path),
+            None => return Ok(Self::default()),
+        };
+
+        let completed = match tokio::fs::read(&path).await {
+            Ok(bytes) => serde_json::from_slice::<Vec<CompletedRecording>>(&bytes)
+                .with_context(|| format!("Failed to parse history file {:?}", path))?
+                .into_iter()
+                .map(|rec| (rec.video_id.clone(), rec))
+                .collect(),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => HashMap::new(),
+            Err(e) => {
+                return Err(e).with_context(|| format!("Failed to read history file {:?}", path))
+            }
+        };
+
+        Ok(Self {
+            path: Some(path),
+            completed,
+        })
+    }
+
+    /// Writes the history to its file, if one was configured.
+    pub async fn save(&self) -> Result<()> {
+        let path = match &self.path {
+            Some(path) => path,
+            None => return Ok(()),
+        };
+
+        let mut completed = self.completed.values().collect::<Vec<_>>();
+        completed.sort_by_key(|rec| rec.finished_at);
+        let json = serde_json::to_vec_pretty(&completed).context("Failed to serialize history")?;
+
+        // Write to a temporary file first so a crash can't corrupt the history
+        let tmp = path.with_extension("tmp");
+        tokio::fs::write(&tmp, json)
+            .await
+            .with_context(|| format!("Failed to write history file {:?}", tmp))?;
+        tokio::fs::rename(&tmp, path)
+            .await
+            .with_context(|| format!("Failed to replace history file {:?}", path))
+    }
+
+    pub fn get(&self, video_id: &str) -> Option<&CompletedRecording> {
+        self.completed.get(video_id)
+    }
+
+    pub fn insert(&mut self, recording: CompletedRecording) {
+        self.completed.insert(recording.video_id.clone(), recording);
+    }
+
+    /// Updates a copy of the recording that was renamed, or forgets it if it
+    /// was removed. Returns whether the history changed.
+    pub fn relocate(&mut self, video_id: &str, from: &str, to: Option<&str>) -> bool {
+        let recording = match self.completed.get_mut(video_id) {
+            Some(recording) => recording,
+            None => return false,
+        };
+        let index = match recording.output_files.iter().position(|f| f == from) {
+            Some(index) => index,
+            None => return false,
+        };
+        match to {
+            Some(to) => recording.output_files[index] = to.to_owned(),
+            None => {
+                recording.output_files.remove(index);
+            }
+        }
+        true
+    }
+}
+
+impl CompletedRecording {
+    /// Returns true if a copy of the recording exists in the directory or one
+    /// of its subdirectories.
+    pub fn has_copy_in(&self, directory: &str) -> bool {
+        self.output_files
+            .iter()
+            .any(|file| Path::new(file).starts_with(directory))
+    }
+
+    /// Returns the first copy of the recording that still exists on disk.
+    pub fn existing_file(&self) -> Option<&Path> {
+        self.output_files
+            .iter()
+            .map(Path::new)
+            .find(|path| path.exists())
+    }
+}
+
+/// Places a copy of `src` into `directory`, keeping its file name. Hardlinks
+/// fall back to copying, e.g. when the directories are on different volumes.
+pub fn duplicate_into(src: &Path, directory: &str, mode: &DuplicateMode) -> Result<PathBuf> {
+    std::fs::create_dir_all(directory)
+        .with_context(|| format!("Failed to create output directory {:?}", directory))?;
+    let filename = src
+        .file_name()
+        .with_context(|| format!("Failed to get filename of {:?}", src))?;
+    let dest = crate::output::unique_path(&Path::new(directory).join(filename));
+
+    if *mode == DuplicateMode::Hardlink {
+        match std::fs::hard_link(src, &dest) {
+            Ok(_) => return Ok(dest),
+            Err(e) => debug!("Failed to hardlink {:?}, trying to copy: {}", dest, e),
+        }
+    }
+
+    std::fs::copy(src, &dest).with_context(|| format!("Failed to copy file to {:?}", dest))?;
+    Ok(dest)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{duplicate_into, CompletedRecording, History};
+    use crate::{config::DuplicateMode, module::recorder::test_dir};
+    use chrono::Utc;
+
+    fn recording(output_files: &[&str]) -> CompletedRecording {
+        CompletedRecording {
+            video_id: "abc".into(),
+            title: "Karaoke".into(),
+            channel_name: "Moona".into(),
+            finished_at: Utc::now(),
+            output_files: output_files.iter().map(|f| f.to_string()).collect(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_history() {
+        let dir = test_dir("history");
+        let path = dir.join("history.json");
+        let path = path.to_str().unwrap();
+
+        // A missing file is an empty history
+        let mut history = History::load(Some(path)).await.unwrap();
+        assert!(history.get("abc").is_none());
+
+        history.insert(recording(&["/videos/moona/Karaoke.mp4"]));
+        history.save().await.unwrap();
+        let mut history = History::load(Some(path)).await.unwrap();
+        let rec = history.get("abc").unwrap();
+        assert_eq!(rec.output_files, ["/videos/moona/Karaoke.mp4"]);
+        assert!(rec.has_copy_in("/videos/moona"));
+        assert!(rec.has_copy_in("/videos"));
+        assert!(!rec.has_copy_in("/videos/moo"));
+        assert!(!rec.has_copy_in("/videos/pekora"));
+        assert!(rec.existing_file().is_none());
+
+        // Copies renamed or removed by post-processing
+        history.insert(recording(&[
+            "/videos/moona/Karaoke.mp4",
+            "/videos/collabs/Karaoke.mp4",
+        ]));
+        assert!(history.relocate(
+            "abc",
+            "/videos/moona/Karaoke.mp4",
+            Some("/videos/moona/Karaoke.mkv")
+        ));
+        assert!(history.relocate("abc", "/videos/collabs/Karaoke.mp4", None));
+        assert!(!history.relocate("abc", "/videos/moona/Karaoke.jpg", None));
+        assert!(!history.relocate("xyz", "/videos/moona/Karaoke.mkv", None));
+        assert_eq!(
+            history.get("abc").unwrap().output_files,
+            ["/videos/moona/Karaoke.mkv"]
+        );
+
+        std::fs::write(path, "not json").unwrap();
+        assert!(History::load(Some(path)).await.is_err());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_duplicate_into() {
+        let dir = test_dir("duplicate");
+        let src = dir.join("moona").join("Karaoke.mp4");
+        std::fs::create_dir_all(src.parent().unwrap()).unwrap();
+        std::fs::write(&src, "video").unwrap();
+        let src_str = src.to_str().unwrap();
+        let rec = recording(&["/gone/Karaoke.mp4", src_str]);
+        assert_eq!(rec.existing_file(), Some(src.as_path()));
+
+        let target = dir.join("collabs");
+        let target = target.to_str().unwrap();
+        let linked = duplicate_into(&src, target, &DuplicateMode::Hardlink).unwrap();
+        assert_eq!(linked, dir.join("collabs").join("Karaoke.mp4"));
+        assert_eq!(std::fs::read_to_string(&linked).unwrap(), "video");
+
+        // Existing files are not overwritten
+        let copied = duplicate_into(&src, target, &DuplicateMode::Copy).unwrap();
+        assert_ne!(copied, linked);
+        assert!(copied.starts_with(target));
+        assert_eq!(std::fs::read_to_string(&copied).unwrap(), "video");
+
+        assert!(duplicate_into(&dir.join("missing.mp4"), target, &DuplicateMode::Copy).is_err());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
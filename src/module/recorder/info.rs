@@ -0,0 +1,334 @@
+use super::YTAStatus;
+use crate::{
+    config::{NetworkConfig, YtarchiveConfig},
+    module::Task,
+    youtube::{self, video::InitialPlayerResponse, YouTube},
+    APP_NAME,
+};
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use std::path::{Path, PathBuf};
+
+/// Everything known about an archived video, written next to it as
+/// `.info.json`.
+#[derive(Debug, Serialize)]
+struct Info<'a> {
+    video_id: &'a str,
+    title: &'a str,
+    /// Missing if the video page couldn't be fetched after the recording.
+    description: Option<&'a str>,
+    keywords: Vec<&'a str>,
+    category: Option<&'a str>,
+    /// Length of the video in seconds, if known.
+    duration: Option<u64>,
+    members_only: Option<bool>,
+    publish_date: Option<&'a str>,
+    scheduled_start: Option<DateTime<Utc>>,
+    actual_start: Option<DateTime<Utc>>,
+    actual_end: Option<DateTime<Utc>>,
+    thumbnail: &'a str,
+    channel: Channel<'a>,
+    recording: Recording<'a>,
+    hoshinova_version: &'static str,
+    ytarchive_version: Option<String>,
+    archived_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Serialize)]
+struct Channel<'a> {
+    id: &'a str,
+    name: &'a str,
+    url: String,
+    picture: Option<&'a str>,
+}
+
+#[derive(Debug, Serialize)]
+struct Recording<'a> {
+    #[serde(flatten)]
+    status: &'a YTAStatus,
+    output_file: String,
+    output_size: Option<u64>,
+}
+
+/// Returns the path of a file next to the recording, replacing its
+/// extension.
+fn sidecar_path(video: &Path, ext: &str) -> PathBuf {
+    video.with_extension(ext)
+}
+
+/// Writes the configured sidecar files next to the recording, and returns
+/// their paths. Failures are logged but don't fail the recording.
+pub async fn write_sidecars(
+    cfg: &YtarchiveConfig,
+    network: &NetworkConfig,
+    task: &Task,
+    status: &YTAStatus,
+    video: &Path,
+) -> Vec<PathBuf> {
+    if !cfg.write_info_json && !cfg.save_thumbnail && !cfg.save_channel_picture {
+        return vec![];
+    }
+
+    let youtube = match youtube::shared().with_network(network) {
+        Ok(youtube) => youtube,
+        Err(e) => {
+            warn!("Failed to create client for sidecar files: {:?}", e);
+            return vec![];
+        }
+    };
+    let mut written = vec![];
+
+    if cfg.write_info_json {
+        let path = sidecar_path(video, "info.json");
+        match write_info_json(&youtube, cfg, task, status, video, &path).await {
+            Ok(_) => written.push(path),
+            Err(e) => warn!("Failed to write {}: {:?}", path.display(), e),
+        }
+    }
+
+    if cfg.save_thumbnail {
+        let path = sidecar_path(video, "jpg");
+        let res =
+            youtube::video::fetch_thumbnail(&youtube, &task.video_id, &task.video_picture).await;
+        match write(&path, res).await {
+            Ok(_) => written.push(path),
+            Err(e) => warn!("Failed to save thumbnail of {}: {:?}", task.video_id, e),
+        }
+    }
+
+    if cfg.save_channel_picture {
+        let path = sidecar_path(video, "channel.jpg");
+        let res = fetch_channel_picture(&youtube, task).await;
+        match write(&path, res).await {
+            Ok(_) => written.push(path),
+            Err(e) => warn!("Failed to save picture of {}: {:?}", task.channel_id, e),
+        }
+    }
+
+    written
+}
+
+async fn write(path: &Path, bytes: Result<Vec<u8>>) -> Result<()> {
+    tokio::fs::write(path, bytes?)
+        .await
+        .with_context(|| format!("Failed to write {:?}", path))
+}
+
+async fn fetch_channel_picture(youtube: &YouTube, task: &Task) -> Result<Vec<u8>> {
+    let url = match &task.channel_picture {
+        Some(url) => url.clone(),
+        None => youtube::channel::fetch_picture_url(youtube, &task.channel_id).await?,
+    };
+    let res = youtube
+        .get(&url)
+        .await
+        .context("Failed to fetch channel picture")?;
+    Ok(res.bytes().await?.to_vec())
+}
+
+async fn write_info_json(
+    youtube: &YouTube,
+    cfg: &YtarchiveConfig,
+    task: &Task,
+    status: &YTAStatus,
+    video: &Path,
+    path: &Path,
+) -> Result<()> {
+    // The video page has more details than the task, but might not be
+    // available anymore
+    let url = format!("https://www.youtube.com/watch?v={}", task.video_id);
+    let ipr = match youtube::video::fetch_initial_player_response(youtube, &url).await {
+        Ok(ipr) => Some(ipr),
+        Err(e) => {
+            warn!("Failed to fetch details of {}: {:?}", task.video_id, e);
+            None
+        }
+    };
+
+    let executable = cfg.executable_path.clone();
+    let ytarchive_version = tokio::task::spawn_blocking(move || crate::test_ytarchive(&executable))
+        .await
+        .ok()
+        .and_then(|res| res.ok());
+
+    let info = info(task, status, video, ipr.as_ref(), ytarchive_version);
+    let json = serde_json::to_vec_pretty(&info).context("Failed to serialize info")?;
+    tokio::fs::write(path, json)
+        .await
+        .with_context(|| format!("Failed to write {:?}", path))
+}
+
+fn info<'a>(
+    task: &'a Task,
+    status: &'a YTAStatus,
+    video: &Path,
+    ipr: Option<&'a InitialPlayerResponse>,
+    ytarchive_version: Option<String>,
+) -> Info<'a> {
+    let microformat = ipr
+        .and_then(|ipr| ipr.microformat.as_ref())
+        .map(|m| &m.player_microformat_renderer);
+    Info {
+        video_id: &task.video_id,
+        title: &task.title,
+        description: ipr.map(|ipr| ipr.video_details.short_description.as_str()),
+        keywords: ipr
+            .map(|ipr| {
+                ipr.video_details
+                    .keywords
+                    .iter()
+                    .map(String::as_str)
+                    .collect()
+            })
+            .unwrap_or_default(),
+        category: microformat.and_then(|m| m.category.as_deref()),
+        duration: ipr.and_then(|ipr| ipr.duration()).map(|d| d.as_secs()),
+        members_only: ipr.map(|ipr| ipr.is_members_only()),
+        publish_date: microformat.and_then(|m| m.publish_date.as_deref()),
+        scheduled_start: task.scheduled_start,
+        actual_start: ipr.and_then(|ipr| ipr.start_time()),
+        actual_end: ipr.and_then(|ipr| ipr.end_time()),
+        thumbnail: &task.video_picture,
+        channel: Channel {
+            id: &task.channel_id,
+            name: &task.channel_name,
+            url: microformat
+                .and_then(|m| m.owner_profile_url.clone())
+                .unwrap_or_else(|| format!("https://www.youtube.com/channel/{}", task.channel_id)),
+            picture: task.channel_picture.as_deref(),
+        },
+        recording: Recording {
+            status,
+            output_file: video.to_string_lossy().into_owned(),
+            output_size: std::fs::metadata(video).map(|m| m.len()).ok(),
+        },
+        hoshinova_version: APP_NAME,
+        ytarchive_version,
+        archived_at: Utc::now(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{info, YTAStatus};
+    use crate::module::{recorder::test_dir, Task};
+    use crate::youtube::video::InitialPlayerResponse;
+    use serde_json::json;
+
+    const IPR: &str = r#"{
+        "videoDetails": {
+            "videoId": "abc",
+            "title": "Karaoke!",
+            "channelId": "UCP0BspO_AMEe3aQqqpo89Dg",
+            "author": "Moona Hoshinova",
+            "thumbnail": { "thumbnails": [] },
+            "lengthSeconds": "3600",
+            "keywords": ["karaoke", "hololive"],
+            "shortDescription": "Singing!"
+        },
+        "playabilityStatus": { "reason": "Join this channel to get access to members-only content" },
+        "microformat": {
+            "playerMicroformatRenderer": {
+                "liveBroadcastDetails": {
+                    "startTimestamp": "2022-03-14T12:00:00+00:00",
+                    "endTimestamp": "2022-03-14T13:00:00+00:00"
+                },
+                "category": "Music",
+                "publishDate": "2022-03-14",
+                "ownerProfileUrl": "http://www.youtube.com/@MoonaHoshinova"
+            }
+        }
+    }"#;
+
+    #[test]
+    fn test_info() {
+        let task = Task {
+            title: "Karaoke!".into(),
+            video_id: "abc".into(),
+            video_picture: "https://i.ytimg.com/vi/abc/maxresdefault.jpg".into(),
+            channel_name: "Moona Hoshinova".into(),
+            channel_id: "UCP0BspO_AMEe3aQqqpo89Dg".into(),
+            channel_picture: None,
+            output_directory: "".into(),
+            scheduled_start: None,
+            recorder: Default::default(),
+            source_channel_id: None,
+        };
+        let mut status = YTAStatus::new();
+        status.parse_line("Selected quality: 1080p60 (h264)");
+        let dir = test_dir("info");
+        let video = dir.join("Karaoke! [abc].mp4");
+        std::fs::write(&video, b"video").unwrap();
+        let ipr: InitialPlayerResponse = serde_json::from_str(IPR).unwrap();
+
+        let value = serde_json::to_value(info(
+            &task,
+            &status,
+            &video,
+            Some(&ipr),
+            Some("0.3.2".into()),
+        ))
+        .unwrap();
+        let keys = value.as_object().unwrap().keys().collect::<Vec<_>>();
+        assert_eq!(
+            keys,
+            [
+                "actual_end",
+                "actual_start",
+                "archived_at",
+                "category",
+                "channel",
+                "description",
+                "duration",
+                "hoshinova_version",
+                "keywords",
+                "members_only",
+                "publish_date",
+                "recording",
+                "scheduled_start",
+                "thumbnail",
+                "title",
+                "video_id",
+                "ytarchive_version",
+            ]
+        );
+        assert_eq!(value["description"], "Singing!");
+        assert_eq!(value["keywords"], json!(["karaoke", "hololive"]));
+        assert_eq!(value["category"], "Music");
+        assert_eq!(value["duration"], 3600);
+        assert_eq!(value["members_only"], true);
+        assert_eq!(value["actual_start"], "2022-03-14T12:00:00Z");
+        assert_eq!(value["actual_end"], "2022-03-14T13:00:00Z");
+        assert_eq!(
+            value["channel"]["url"],
+            "http://www.youtube.com/@MoonaHoshinova"
+        );
+        assert_eq!(value["ytarchive_version"], "0.3.2");
+
+        // The status of the recording is flattened into it
+        let recording = value["recording"].as_object().unwrap();
+        for key in ["state", "video_quality", "mode", "vod", "last_update"] {
+            assert!(recording.contains_key(key), "Missing {}", key);
+        }
+        assert!(!recording.contains_key("status"));
+        assert_eq!(recording["video_quality"], "1080p60 (h264)");
+        assert_eq!(recording["output_size"], 5);
+        assert_eq!(
+            recording["output_file"],
+            video.to_string_lossy().into_owned()
+        );
+
+        // Without the video page, only the task is known
+        let value = serde_json::to_value(info(&task, &status, &video, None, None)).unwrap();
+        assert_eq!(value["description"], json!(null));
+        assert_eq!(value["keywords"], json!([]));
+        assert_eq!(value["members_only"], json!(null));
+        assert_eq!(
+            value["channel"]["url"],
+            "https://www.youtube.com/channel/UCP0BspO_AMEe3aQqqpo89Dg"
+        );
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
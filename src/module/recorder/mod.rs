@@ -0,0 +1,1744 @@
+use super::{
+    Archived, Duplicate, DuplicateReason, Message, Module, Notification, ScheduledTask,
+    SystemEvent, SystemEventKind, Task, TaskStatus,
+};
+use crate::msgbus::BusTx;
+use crate::{
+    config::{Config, DuplicateMode, RecorderOverrides, RecordingMode, VodConfig, YtarchiveConfig},
+    module::RecordingStatus,
+    network,
+    output::{self, OutputTemplate},
+};
+use anyhow::{anyhow, Context, Result};
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use lazy_static::lazy_static;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    process::{ExitStatus, Stdio},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+};
+use tokio::{
+    io::{AsyncReadExt, BufReader},
+    sync::{mpsc, watch, Notify, RwLock},
+    task::JoinHandle,
+};
+use ts_rs::TS;
+
+pub mod disk;
+mod history;
+mod info;
+
+pub struct YTArchive {
+    config: Arc<RwLock<Config>>,
+    /// Videos being recorded, along with the output directories of other tasks
+    /// that requested the same video.
+    active_ids: Arc<RwLock<HashMap<String, Vec<String>>>>,
+    history: Arc<RwLock<history::History>>,
+    /// Tasks waiting for their time to be spawned.
+    schedule: Arc<RwLock<Vec<(ScheduledTask, SpawnTask)>>>,
+    /// Recordings being placed into other output directories, by video ID
+    /// and directory.
+    linking: Arc<RwLock<HashSet<(String, String)>>>,
+}
+
+/// How a task for a video that was already requested is handled.
+#[derive(Debug, PartialEq)]
+enum Deduplication {
+    /// Nothing needs to be done.
+    Skip(DuplicateReason),
+    /// The recording at the path is placed into the task's output directory.
+    Link(PathBuf),
+}
+
+/// How a ytarchive run ended.
+enum Outcome {
+    /// The recording finished and was moved to the given path, along with the
+    /// sidecar files written next to it.
+    Finished(PathBuf, Vec<PathBuf>),
+    /// The stream starts too far in the future, so ytarchive was stopped to be
+    /// started again closer to the given time.
+    Deferred(DateTime<Utc>),
+    /// The recording did not finish.
+    Stopped,
+}
+
+/// Builds the ytarchive command line for a video. The global arguments come
+/// first so the overrides of the channel or task win, and the thread count
+/// replaces any given in the global arguments. The recording mode decides the
+/// quality that is asked for.
+fn ytarchive_args(
+    cfg: &YtarchiveConfig,
+    overrides: &RecorderOverrides,
+    cookies: Option<&str>,
+    video_id: &str,
+) -> Vec<String> {
+    let mut args = cfg.args.clone();
+
+    if let Some(threads) = overrides.threads {
+        let mut i = 0;
+        while i < args.len() {
+            if args[i] == "--threads" {
+                args.drain(i..(i + 2).min(args.len()));
+            } else if args[i].starts_with("--threads=") {
+                args.remove(i);
+            } else {
+                i += 1;
+            }
+        }
+        args.push("--threads".to_string());
+        args.push(threads.to_string());
+    }
+    args.extend(overrides.args.iter().cloned());
+
+    let has = |args: &[String], flags: &[&str]| args.iter().any(|a| flags.contains(&a.as_str()));
+
+    // Add the --wait flag if not present
+    if !has(&args, &["-w", "--wait"]) {
+        args.push("--wait".to_string());
+    }
+
+    // Add the cookies unless they are already passed in the args
+    if let Some(cookies) = cookies {
+        if !has(&args, &["-c", "--cookies"]) {
+            args.push("--cookies".to_string());
+            args.push(cookies.to_string());
+        }
+    }
+
+    let quality = overrides
+        .quality
+        .clone()
+        .unwrap_or_else(|| cfg.quality.clone());
+    let quality = match overrides.mode.as_ref().unwrap_or(&RecordingMode::Full) {
+        RecordingMode::Full => quality,
+        RecordingMode::AudioOnly => "audio_only".to_string(),
+        RecordingMode::VideoOnly => {
+            if !has(&args, &["--video-only"]) {
+                args.push("--video-only".to_string());
+            }
+            quality
+        }
+        // ytarchive picks the first available quality of a list separated
+        // by slashes, and asks which one to use if none are
+        RecordingMode::Ladder(qualities) => {
+            let mut qualities = qualities.clone();
+            if !qualities.iter().any(|q| q == "best") {
+                qualities.push("best".to_string());
+            }
+            qualities.join("/")
+        }
+    };
+
+    args.push(format!("https://youtu.be/{}", video_id));
+    args.push(quality);
+    args
+}
+
+/// Builds the yt-dlp command line to download a video whose stream already
+/// ended. The final file is printed the same way ytarchive does, and the
+/// recording mode decides the format unless one is given in the args.
+fn ytdlp_args(
+    vod: &VodConfig,
+    mode: &RecordingMode,
+    cookies: Option<&str>,
+    video_id: &str,
+) -> Vec<String> {
+    let mut args = vod.args.clone();
+    let has = |args: &[String], flags: &[&str]| args.iter().any(|a| flags.contains(&a.as_str()));
+
+    // Add the cookies unless they are already passed in the args
+    if let Some(cookies) = cookies {
+        if !has(&args, &["--cookies"]) {
+            args.push("--cookies".to_string());
+            args.push(cookies.to_string());
+        }
+    }
+
+    if !has(&args, &["-f", "--format"]) {
+        let format = match mode {
+            RecordingMode::AudioOnly => Some("bestaudio"),
+            RecordingMode::VideoOnly => Some("bestvideo"),
+            RecordingMode::Full | RecordingMode::Ladder(_) => None,
+        };
+        if let Some(format) = format {
+            args.push("--format".to_string());
+            args.push(format.to_string());
+        }
+    }
+
+    args.extend(
+        [
+            "--newline",
+            "--progress",
+            "--print",
+            "after_move:Final file: %(filepath)s",
+        ]
+        .map(String::from),
+    );
+    args.push(format!("https://www.youtube.com/watch?v={}", video_id));
+    args
+}
+
+macro_rules! read_line {
+    ($reader:expr, $tx:expr) => {{
+        // Read bytes until a \r or \n is returned
+        let mut bytes = Vec::new();
+        loop {
+            match $reader.read_u8().await {
+                Ok(byte) => {
+                    if byte == b'\r' || byte == b'\n' {
+                        break;
+                    }
+                    bytes.push(byte);
+                }
+                _ => break,
+            }
+        }
+
+        // Skip if there are no bytes
+        if bytes.is_empty() {
+            continue;
+        }
+
+        // Convert to a string
+        let line = match std::str::from_utf8(&bytes) {
+            Ok(line) => line.to_owned(),
+            Err(e) => {
+                trace!("Failed to read utf8: {:?}", e);
+                break;
+            }
+        };
+
+        // Send the line to the channel
+        if let Err(e) = $tx.send(line).await {
+            trace!("Failed to send line: {:?}", e);
+            break;
+        }
+    }};
+}
+
+/// A running ytarchive or yt-dlp, with its output read line by line.
+struct Process {
+    name: &'static str,
+    /// Lines of stdout and stderr.
+    lines: mpsc::Receiver<String>,
+    /// Set once the process has exited.
+    done: Arc<AtomicBool>,
+    /// Signal to stop the process early.
+    kill: Arc<Notify>,
+    h_wait: JoinHandle<std::io::Result<ExitStatus>>,
+    h_stdout: JoinHandle<()>,
+    h_stderr: JoinHandle<()>,
+}
+
+impl Process {
+    fn spawn(
+        task_name: &str,
+        name: &'static str,
+        command: &mut tokio::process::Command,
+    ) -> Result<Self> {
+        let mut process = command
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .with_context(|| format!("Failed to start {}", name))?;
+        // Grab stdout/stderr byte iterators
+        let mut stdout = BufReader::new(
+            process
+                .stdout
+                .take()
+                .ok_or(anyhow!("Failed to take stdout"))?,
+        );
+        let mut stderr = BufReader::new(
+            process
+                .stderr
+                .take()
+                .ok_or(anyhow!("Failed to take stderr"))?,
+        );
+
+        // Create a channel to consolidate stdout and stderr
+        let (tx, rx) = mpsc::channel(1);
+
+        // Flag to mark when the process has exited
+        let done = Arc::from(AtomicBool::new(false));
+
+        // Read stdout
+        let h_stdout = tokio::spawn({
+            let done = done.clone();
+            let task_name = task_name.to_string();
+            let tx = tx.clone();
+            async move {
+                while !done.load(Ordering::Relaxed) {
+                    read_line!(&mut stdout, tx);
+                }
+                trace!("{} stdout reader exited", task_name);
+            }
+        });
+
+        // Read stderr
+        let h_stderr = tokio::spawn({
+            let done = done.clone();
+            let task_name = task_name.to_string();
+            let tx = tx.clone();
+            async move {
+                while !done.load(Ordering::Relaxed) {
+                    read_line!(&mut stderr, tx);
+                }
+                trace!("{} stderr reader exited", task_name);
+            }
+        });
+
+        let kill = Arc::new(Notify::new());
+
+        // Wait for the process to exit
+        let h_wait = tokio::spawn({
+            let done = done.clone();
+            let kill = kill.clone();
+            let task_name = task_name.to_string();
+            async move {
+                let result = tokio::select! {
+                    result = process.wait() => result,
+                    _ = kill.notified() => {
+                        debug!("{} Stopping {}", task_name, name);
+                        if let Err(e) = process.kill().await {
+                            warn!("{} Failed to stop {}: {:?}", task_name, name, e);
+                        }
+                        process.wait().await
+                    }
+                };
+
+                // Wait a bit for the stdout to be completely read
+                tokio::time::sleep(tokio::time::Duration::from_millis(100)).await;
+
+                // Stop threads
+                done.store(true, Ordering::Relaxed);
+                debug!("{} Process exited with {:?}", task_name, result);
+
+                // Send a blank message to unblock the status monitor thread
+                let _ = tx.send("".into());
+
+                result
+            }
+        });
+
+        Ok(Self {
+            name,
+            lines: rx,
+            done,
+            kill,
+            h_wait,
+            h_stdout,
+            h_stderr,
+        })
+    }
+
+    /// Waits for the process and its readers to finish.
+    async fn wait(self, task_name: &str) {
+        let (r_wait, r_stdout, r_stderr) =
+            futures::join!(self.h_wait, self.h_stdout, self.h_stderr);
+        trace!("{} Process monitor exited: {:?}", task_name, r_wait);
+        trace!("{} Stdout monitor quit: {:?}", task_name, r_stdout);
+        trace!("{} Stderr monitor quit: {:?}", task_name, r_stderr);
+    }
+}
+
+impl YTArchive {
+    /// Checks whether the video of the task was already requested. Returns
+    /// `None` if the task should be recorded.
+    async fn deduplicate(&self, task: &Task) -> Option<Deduplication> {
+        // Merge with the active task if there is one
+        if let Some(extra_dirs) = self.active_ids.write().await.get_mut(&task.video_id) {
+            if extra_dirs.contains(&task.output_directory) {
+                return Some(Deduplication::Skip(DuplicateReason::Active));
+            }
+            extra_dirs.push(task.output_directory.clone());
+            return Some(Deduplication::Skip(DuplicateReason::Merged));
+        }
+
+        // Otherwise check if it was recorded before
+        let key = (task.video_id.clone(), task.output_directory.clone());
+        let mut linking = self.linking.write().await;
+        let history = self.history.read().await;
+        let recording = history.get(&task.video_id)?;
+        if recording.has_copy_in(&task.output_directory) || linking.contains(&key) {
+            return Some(Deduplication::Skip(DuplicateReason::Recorded));
+        }
+        // Post-processing removed every copy, e.g. after uploading them
+        if recording.output_files.is_empty() {
+            info!(
+                "Recording of {} was removed by post-processing, not recording again",
+                task.video_id
+            );
+            return Some(Deduplication::Skip(DuplicateReason::Recorded));
+        }
+        match recording.existing_file() {
+            Some(src) => {
+                linking.insert(key);
+                Some(Deduplication::Link(src.to_owned()))
+            }
+            None => {
+                warn!(
+                    "Recording of {} no longer exists, recording again",
+                    task.video_id
+                );
+                None
+            }
+        }
+    }
+
+    /// Places the existing recording into the task's output directory and
+    /// remembers the new copy. Copying can take a while, so it is done on a
+    /// blocking thread.
+    async fn link(
+        history: Arc<RwLock<history::History>>,
+        linking: Arc<RwLock<HashSet<(String, String)>>>,
+        src: PathBuf,
+        task: Task,
+        mode: DuplicateMode,
+        tx: BusTx<Message>,
+    ) {
+        let dir = task.output_directory.clone();
+        let result =
+            tokio::task::spawn_blocking(move || history::duplicate_into(&src, &dir, &mode))
+                .await
+                .map_err(anyhow::Error::from)
+                .and_then(|result| result);
+        match result {
+            Ok(dest) => {
+                info!(
+                    "Placed existing recording of {} into {}",
+                    task.video_id,
+                    dest.display()
+                );
+                let mut history = history.write().await;
+                if let Some(recording) = history.get(&task.video_id) {
+                    let mut recording = recording.clone();
+                    recording
+                        .output_files
+                        .push(dest.to_string_lossy().into_owned());
+                    history.insert(recording);
+                    if let Err(e) = history.save().await {
+                        error!("Failed to save history: {:?}", e);
+                    }
+                }
+            }
+            Err(e) => error!("Failed to duplicate {}: {:?}", task.video_id, e),
+        }
+        linking
+            .write()
+            .await
+            .remove(&(task.video_id.clone(), task.output_directory.clone()));
+
+        warn!(
+            "Task {} is a duplicate ({:?})",
+            task.video_id,
+            DuplicateReason::Linked
+        );
+        let _ = tx
+            .send(Message::Duplicate(Duplicate {
+                task,
+                reason: DuplicateReason::Linked,
+                timestamp: Utc::now(),
+            }))
+            .await;
+    }
+
+    /// Adds the task to the schedule if its stream starts later than the lead
+    /// time. Returns the task back if it should be spawned right away.
+    async fn schedule(
+        schedule: &RwLock<Vec<(ScheduledTask, SpawnTask)>>,
+        task: SpawnTask,
+    ) -> Option<SpawnTask> {
+        let spawn_at = match (
+            task.task.scheduled_start,
+            task.cfg.ytarchive.schedule_lead_time,
+        ) {
+            (Some(start), Some(lead)) => start - chrono::Duration::from_std(lead).ok()?,
+            _ => return Some(task),
+        };
+        if spawn_at <= Utc::now() {
+            return Some(task);
+        }
+
+        info!(
+            "Scheduling {} to start at {}",
+            task.task.video_id,
+            spawn_at.to_rfc3339()
+        );
+        let scheduled = ScheduledTask {
+            task: task.task.clone(),
+            spawn_at,
+        };
+        if let Err(e) = task.tx.send(Message::Scheduled(scheduled.clone())).await {
+            debug!("Failed to send scheduled task: {:?}", e);
+        }
+        schedule.write().await.push((scheduled, task));
+        None
+    }
+
+    /// Runs ytarchive for the task.
+    async fn record(cfg: Config, task: Task, bus: &mut BusTx<Message>) -> Result<Outcome> {
+        let task_name = format!("[{}][{}][{}]", task.video_id, task.channel_name, task.title);
+        // Settings of the task take precedence over those of its channel
+        let overrides = match cfg.channel(task.source_channel()) {
+            Some(channel) => task.recorder.clone().or(&channel.recorder),
+            None => task.recorder.clone(),
+        };
+        let template = overrides
+            .output_template
+            .clone()
+            .or_else(|| cfg.ytarchive.output_template.clone());
+
+        let cookies = cfg.cookies_for(task.source_channel()).map(String::from);
+
+        // Ensure the working directory exists
+        let network = cfg.network_for(task.source_channel()).clone();
+        let cfg = cfg.ytarchive;
+        tokio::fs::create_dir_all(&cfg.working_directory)
+            .await
+            .context("Failed to create working directory")?;
+
+        // Ensure the output directory exists
+        tokio::fs::create_dir_all(&task.output_directory)
+            .await
+            .context("Failed to create output directory")?;
+
+        // The cookies are passed as an absolute path since ytarchive runs in
+        // the working directory
+        let cookies = match &cookies {
+            Some(cookies) => Some(
+                fs::canonicalize(cookies)
+                    .with_context(|| format!("Failed to find cookie file {}", cookies))?
+                    .to_string_lossy()
+                    .into_owned(),
+            ),
+            None => None,
+        };
+        let mut args = network::ytarchive_args(&network);
+        args.extend(ytarchive_args(
+            &cfg,
+            &overrides,
+            cookies.as_deref(),
+            &task.video_id,
+        ));
+
+        // Start the process
+        debug!("{} Starting ytarchive with args {:?}", task_name, args);
+        let mut process = Process::spawn(
+            &task_name,
+            "ytarchive",
+            tokio::process::Command::new(&cfg.executable_path)
+                .args(args)
+                .envs(network::ytarchive_env(&network))
+                .current_dir(&cfg.working_directory),
+        )?;
+
+        // Notification for the task at the given ytarchive status
+        let notify = |task_status, status: &YTAStatus| {
+            Message::ToNotify(Notification {
+                task: task.clone(),
+                status: task_status,
+                recording: Some(status.clone()),
+                output_file: None,
+                output_size: None,
+            })
+        };
+
+        // Parse each line
+        let mut status = YTAStatus::new();
+        status.mode = overrides.mode.clone().unwrap_or_default();
+        let mut parse: fn(&mut YTAStatus, &str) = YTAStatus::parse_line;
+        let mut deferred = None;
+        loop {
+            while let Some(line) = process.lines.recv().await {
+                // Stop when done
+                if process.done.load(Ordering::Relaxed) {
+                    break;
+                }
+
+                trace!("{}[{}:out] {}", task_name, process.name, line);
+
+                let old = status.clone();
+                parse(&mut status, &line);
+
+                // Push the current status to the bus
+                if let Err(_) = bus
+                    .send(Message::RecordingStatus(RecordingStatus {
+                        task: task.clone(),
+                        status: status.clone(),
+                    }))
+                    .await
+                {
+                    break;
+                }
+
+                // Check if status changed
+                if old.state == status.state {
+                    continue;
+                }
+
+                // Stop waiting if the stream starts well after the lead time
+                if let YTAState::Waiting(Some(start)) = &status.state {
+                    if should_defer(*start, cfg.schedule_lead_time, Utc::now()) {
+                        info!(
+                            "{} Stream starts at {}, deferring",
+                            task_name,
+                            start.to_rfc3339()
+                        );
+                        deferred = Some(*start);
+                        process.kill.notify_one();
+                        continue;
+                    }
+                }
+
+                let message = match status.state {
+                    YTAState::Waiting(_) => {
+                        info!("{} Waiting for stream to go live", task_name);
+                        Some(notify(TaskStatus::Waiting, &status))
+                    }
+                    YTAState::Recording => {
+                        info!("{} Recording started", task_name);
+                        Some(notify(TaskStatus::Recording, &status))
+                    }
+                    YTAState::Finished => {
+                        // Notified once the file is moved to the output directory
+                        info!("{} Recording finished", task_name);
+                        None
+                    }
+                    YTAState::AlreadyProcessed if cfg.vod.is_none() => {
+                        info!("{} Video already processed, skipping", task_name);
+                        None
+                    }
+                    YTAState::Interrupted => {
+                        info!("{} Recording failed: interrupted", task_name);
+                        Some(notify(TaskStatus::Failed, &status))
+                    }
+                    YTAState::LoginRequired => {
+                        if let Some(cookies) = &cookies {
+                            warn!(
+                                "{} Recording failed: login required, \
+                                 cookies in {} may have expired",
+                                task_name, cookies
+                            );
+                            let event = SystemEvent::new(
+                                SystemEventKind::CookiesExpired,
+                                format!(
+                                    "{} could not be recorded with the cookies in {}, \
+                                     they may have expired",
+                                    task.title, cookies
+                                ),
+                            );
+                            let _ = bus.send(Message::SystemEvent(event)).await;
+                        } else {
+                            warn!(
+                                "{} Recording failed: login required, \
+                                 configure cookies to record members-only streams",
+                                task_name
+                            );
+                        }
+                        Some(notify(TaskStatus::Failed, &status))
+                    }
+                    _ => None,
+                };
+
+                if let Some(message) = message {
+                    // Exit the loop if message failed to send
+                    if let Err(_) = bus.send(message).await {
+                        break;
+                    }
+                }
+            }
+            process.wait(&task_name).await;
+
+            // Download streams that ended before they could be recorded with
+            // yt-dlp instead, once
+            let ended = matches!(status.state, YTAState::Ended | YTAState::AlreadyProcessed);
+            let vod = match &cfg.vod {
+                Some(vod) if ended && !status.vod => vod,
+                _ => break,
+            };
+            info!(
+                "{} Stream has already ended, downloading it with yt-dlp",
+                task_name
+            );
+            let mut args = network::ytdlp_args(&network);
+            args.extend(ytdlp_args(
+                vod,
+                &status.mode,
+                cookies.as_deref(),
+                &task.video_id,
+            ));
+            debug!("{} Starting yt-dlp with args {:?}", task_name, args);
+            process = Process::spawn(
+                &task_name,
+                "yt-dlp",
+                tokio::process::Command::new(&vod.executable_path)
+                    .args(args)
+                    .current_dir(&cfg.working_directory),
+            )?;
+            status.vod = true;
+            parse = YTAStatus::parse_vod_line;
+        }
+
+        trace!("{} Status loop exited: {:?}", task_name, status);
+
+        if let Some(start) = deferred {
+            return Ok(Outcome::Deferred(start));
+        }
+
+        // Skip moving files if it didn't finish
+        if status.state != YTAState::Finished {
+            return Ok(Outcome::Stopped);
+        }
+
+        // Move the video to the output directory
+        let destpath = match Self::move_to_output(&task_name, &task, &status, template.as_ref()) {
+            Ok(destpath) => destpath,
+            Err(e) => {
+                let _ = bus.send(notify(TaskStatus::Failed, &status)).await;
+                return Err(e);
+            }
+        };
+
+        // Write the metadata next to the video
+        let sidecars = info::write_sidecars(&cfg, &network, &task, &status, &destpath).await;
+
+        // Notify with the details of the final file
+        let output_size = fs::metadata(&destpath).map(|m| m.len()).ok();
+        let _ = bus
+            .send(Message::ToNotify(Notification {
+                task: task.clone(),
+                status: TaskStatus::Done,
+                recording: Some(status.clone()),
+                output_file: Some(destpath.to_string_lossy().into_owned()),
+                output_size,
+            }))
+            .await;
+
+        Ok(Outcome::Finished(destpath, sidecars))
+    }
+
+    /// Moves the final file from the working directory into the output
+    /// directory of the task, at the path given by the template if any.
+    fn move_to_output(
+        task_name: &str,
+        task: &Task,
+        status: &YTAStatus,
+        template: Option<&OutputTemplate>,
+    ) -> Result<PathBuf> {
+        let frompath = status
+            .output_file
+            .as_ref()
+            .ok_or(anyhow!("ytarchive did not emit an output file"))?;
+        let frompath = Path::new(frompath);
+        let filename = frompath
+            .file_name()
+            .ok_or(anyhow!("Failed to get filename"))?;
+        let destpath = match template {
+            Some(template) => {
+                let now = Utc::now();
+                template.render(&output::Vars {
+                    title: &task.title,
+                    video_id: &task.video_id,
+                    channel_name: &task.channel_name,
+                    channel_id: &task.channel_id,
+                    filename: &frompath
+                        .file_stem()
+                        .map(|s| s.to_string_lossy())
+                        .unwrap_or_default(),
+                    ext: &frompath
+                        .extension()
+                        .map(|e| e.to_string_lossy())
+                        .unwrap_or_default(),
+                    upload_date: task.scheduled_start.unwrap_or(now),
+                    date: now,
+                })
+            }
+            None => PathBuf::from(filename),
+        };
+        let destpath = Path::new(&task.output_directory).join(destpath);
+
+        // Create the directories of the template, and don't overwrite
+        // existing files
+        if let Some(parent) = destpath.parent() {
+            fs::create_dir_all(parent)
+                .with_context(|| format!("Failed to create directory {:?}", parent))?;
+        }
+        let destpath = output::unique_path(&destpath);
+
+        // Try to rename the file into the output directory
+        if let Err(_) = fs::rename(frompath, &destpath) {
+            debug!(
+                "{} Failed to rename file to output, trying to copy",
+                task_name,
+            );
+
+            // Keep the file in the working directory if it doesn't fit
+            let size = fs::metadata(frompath)
+                .with_context(|| format!("Failed to read output file: {:?}", frompath))?
+                .len();
+            let available = disk::available_space(&task.output_directory)?;
+            if available < size {
+                return Err(anyhow!(
+                    "Not enough space in {} to move {} ({} needed, {} free)",
+                    task.output_directory,
+                    frompath.display(),
+                    bytesize::ByteSize(size).to_string_as(true),
+                    bytesize::ByteSize(available).to_string_as(true),
+                ));
+            }
+
+            // Copy the file into the output directory
+            fs::copy(frompath, &destpath)
+                .with_context(|| format!("Failed to copy file to output: {:?}", destpath))?;
+            info!(
+                "{} Copied output file to {}, removing original",
+                task_name,
+                destpath.display(),
+            );
+            fs::remove_file(frompath)
+                .with_context(|| format!("Failed to remove original file: {:?}", frompath))?;
+        }
+
+        info!("{} Moved output file to {}", task_name, destpath.display());
+        Ok(destpath)
+    }
+}
+
+struct SpawnTask {
+    task: Task,
+    cfg: Config,
+    tx: BusTx<Message>,
+}
+
+/// Returns whether ytarchive, waiting for a stream that starts at the given
+/// time, should be stopped to be started again closer to the start.
+fn should_defer(
+    start: DateTime<Utc>,
+    lead: Option<std::time::Duration>,
+    now: DateTime<Utc>,
+) -> bool {
+    let lead = match lead.and_then(|lead| chrono::Duration::from_std(lead).ok()) {
+        Some(lead) => lead,
+        None => return false,
+    };
+    // Leave some room so a stream that is about due isn't deferred
+    start - lead > now + chrono::Duration::minutes(1)
+}
+
+/// Removes the tasks that are due from the schedule.
+fn take_due(schedule: &mut Vec<(ScheduledTask, SpawnTask)>, now: DateTime<Utc>) -> Vec<SpawnTask> {
+    let (due, waiting) = schedule
+        .drain(..)
+        .partition::<Vec<_>, _>(|(scheduled, _)| scheduled.spawn_at <= now);
+    *schedule = waiting;
+    due.into_iter().map(|(_, task)| task).collect()
+}
+
+#[async_trait]
+impl Module for YTArchive {
+    fn new(config: Arc<RwLock<Config>>) -> Self {
+        let active_ids = Arc::new(RwLock::new(HashMap::new()));
+        let history = Arc::new(RwLock::new(history::History::default()));
+        let schedule = Arc::new(RwLock::new(Vec::new()));
+        let linking = Arc::new(RwLock::new(HashSet::new()));
+        Self {
+            config,
+            active_ids,
+            history,
+            schedule,
+            linking,
+        }
+    }
+
+    async fn run(&self, tx: &BusTx<Message>, rx: &mut mpsc::Receiver<Message>) -> Result<()> {
+        // Load the recording history
+        {
+            let cfg = self.config.read().await;
+            *self.history.write().await =
+                history::History::load(cfg.ytarchive.history_file.as_deref()).await?;
+        }
+
+        // Create a spawn queue
+        let (spawn_tx, mut spawn_rx) = mpsc::unbounded_channel::<SpawnTask>();
+
+        // Signal to stop the scheduler when the bus closes
+        let (stop_tx, mut stop_rx) = watch::channel(false);
+
+        // Future to handle spawning new tasks
+        let active_ids = self.active_ids.clone();
+        let history = self.history.clone();
+        let schedule = self.schedule.clone();
+        let f_spawner = async move {
+            while let Some(mut task) = spawn_rx.recv().await {
+                let active_ids = active_ids.clone();
+                let history = history.clone();
+                let schedule = schedule.clone();
+                let delay = task.cfg.ytarchive.delay_start;
+                let mode = task.cfg.ytarchive.duplicate_mode.clone();
+
+                debug!("Spawning thread for task: {:?}", task.task);
+                tokio::spawn(async move {
+                    let video_id = task.task.video_id.clone();
+
+                    // Make sure there is enough disk space to record
+                    if let Err(e) =
+                        disk::ensure_space(&task.cfg, &task.task, &active_ids, &task.tx).await
+                    {
+                        error!("Failed to record task: {:?}", e);
+                        let _ = task
+                            .tx
+                            .send(Message::ToNotify(Notification {
+                                task: task.task.clone(),
+                                status: TaskStatus::Failed,
+                                recording: None,
+                                output_file: None,
+                                output_size: None,
+                            }))
+                            .await;
+                        active_ids.write().await.remove(&video_id);
+                        return;
+                    }
+
+                    let result =
+                        YTArchive::record(task.cfg.clone(), task.task.clone(), &mut task.tx).await;
+
+                    let (destpath, sidecars) = match result {
+                        Ok(Outcome::Finished(destpath, sidecars)) => (destpath, sidecars),
+                        Ok(Outcome::Deferred(start)) => {
+                            // Keep the task active, and try again later
+                            task.task.scheduled_start = Some(start);
+                            if let Some(task) = YTArchive::schedule(&schedule, task).await {
+                                warn!("Task {} could not be scheduled", task.task.video_id);
+                                active_ids.write().await.remove(&video_id);
+                            }
+                            return;
+                        }
+                        Ok(Outcome::Stopped) => {
+                            active_ids.write().await.remove(&video_id);
+                            return;
+                        }
+                        Err(e) => {
+                            error!("Failed to record task: {:?}", e);
+                            active_ids.write().await.remove(&video_id);
+                            return;
+                        }
+                    };
+                    let extra_dirs = active_ids
+                        .write()
+                        .await
+                        .remove(&video_id)
+                        .unwrap_or_default();
+
+                    // Place the recording into the directories of merged tasks
+                    let mut output_files = vec![destpath.to_string_lossy().into_owned()];
+                    for dir in extra_dirs {
+                        match history::duplicate_into(&destpath, &dir, &mode) {
+                            Ok(dest) => output_files.push(dest.to_string_lossy().into_owned()),
+                            Err(e) => error!("Failed to duplicate {}: {:?}", video_id, e),
+                        }
+                    }
+
+                    // Remember the recording
+                    {
+                        let mut history = history.write().await;
+                        history.insert(history::CompletedRecording {
+                            video_id,
+                            title: task.task.title.clone(),
+                            channel_name: task.task.channel_name.clone(),
+                            finished_at: Utc::now(),
+                            output_files,
+                        });
+                        if let Err(e) = history.save().await {
+                            error!("Failed to save history: {:?}", e);
+                        }
+                    }
+
+                    // Hand the recording over to post-processing
+                    let _ = task
+                        .tx
+                        .send(Message::Archived(Archived {
+                            task: task.task,
+                            output_file: destpath.to_string_lossy().into_owned(),
+                            sidecars: sidecars
+                                .iter()
+                                .map(|p| p.to_string_lossy().into_owned())
+                                .collect(),
+                        }))
+                        .await;
+                });
+
+                // Wait a bit before starting the next task
+                tokio::time::sleep(delay).await;
+            }
+
+            Ok::<(), anyhow::Error>(())
+        };
+
+        // Future to move scheduled tasks to the spawn queue once they're due
+        let schedule = self.schedule.clone();
+        let scheduler_tx = spawn_tx.clone();
+        let f_scheduler = async move {
+            loop {
+                tokio::select! {
+                    _ = stop_rx.changed() => break,
+                    _ = tokio::time::sleep(tokio::time::Duration::from_secs(1)) => (),
+                }
+
+                let due = take_due(&mut *schedule.write().await, Utc::now());
+                for task in due {
+                    debug!("Scheduled task {} is due", task.task.video_id);
+                    if scheduler_tx.send(task).is_err() {
+                        debug!("Spawn queue closed, exiting");
+                        return Ok(());
+                    }
+                }
+            }
+
+            Ok::<(), anyhow::Error>(())
+        };
+
+        // Future to handle incoming messages
+        let f_message = async move {
+            while let Some(message) = rx.recv().await {
+                match message {
+                    Message::ToRecord(task) => {
+                        let cfg = self.config.read().await;
+                        let cfg = cfg.clone();
+
+                        // Check if the video was already requested
+                        match self.deduplicate(&task).await {
+                            Some(Deduplication::Skip(reason)) => {
+                                warn!("Task {} is a duplicate ({:?})", task.video_id, reason);
+                                let duplicate = Duplicate {
+                                    task,
+                                    reason,
+                                    timestamp: Utc::now(),
+                                };
+                                if tx.send(Message::Duplicate(duplicate)).await.is_err() {
+                                    break;
+                                }
+                                continue;
+                            }
+                            Some(Deduplication::Link(src)) => {
+                                tokio::spawn(YTArchive::link(
+                                    self.history.clone(),
+                                    self.linking.clone(),
+                                    src,
+                                    task,
+                                    cfg.ytarchive.duplicate_mode.clone(),
+                                    tx.clone(),
+                                ));
+                                continue;
+                            }
+                            None => (),
+                        }
+                        self.active_ids
+                            .write()
+                            .await
+                            .insert(task.video_id.clone(), vec![]);
+
+                        // Hold the task back if its stream starts much later
+                        let tx = tx.clone();
+                        let task = SpawnTask { task, cfg, tx };
+                        let task = match YTArchive::schedule(&self.schedule, task).await {
+                            Some(task) => task,
+                            None => continue,
+                        };
+
+                        debug!("Adding task to spawn queue: {:?}", task.task);
+                        if let Err(_) = spawn_tx.send(task) {
+                            debug!("Spawn queue closed, exiting");
+                            break;
+                        }
+                    }
+                    Message::Relocated(relocated) => {
+                        let mut history = self.history.write().await;
+                        let to = relocated.to.as_deref();
+                        if history.relocate(&relocated.video_id, &relocated.from, to) {
+                            if let Err(e) = history.save().await {
+                                error!("Failed to save history: {:?}", e);
+                            }
+                        }
+                    }
+                    _ => (),
+                }
+            }
+
+            // Stop the scheduler, which closes the spawn queue
+            drop(spawn_tx);
+            let _ = stop_tx.send(true);
+            Ok::<(), anyhow::Error>(())
+        };
+
+        // Run the futures
+        tokio::try_join!(f_spawner, f_scheduler, f_message)?;
+
+        debug!("YTArchive module finished");
+        Ok(())
+    }
+}
+
+/// The current state of ytarchive.
+#[derive(Debug, Clone, TS, Serialize, Deserialize)]
+#[ts(export, export_to = "web/src/bindings/")]
+pub struct YTAStatus {
+    version: Option<String>,
+    state: YTAState,
+    last_output: Option<String>,
+    last_update: chrono::DateTime<chrono::Utc>,
+    video_fragments: Option<u32>,
+    audio_fragments: Option<u32>,
+    total_size: Option<String>,
+    video_quality: Option<String>,
+    /// What was asked to be recorded, `video_quality` is what ytarchive
+    /// picked.
+    mode: RecordingMode,
+    /// Whether the stream had already ended and is downloaded by yt-dlp.
+    vod: bool,
+    /// Percentage of the current file downloaded by yt-dlp.
+    progress: Option<f32>,
+    output_file: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, TS, Serialize, Deserialize)]
+#[ts(export, export_to = "web/src/bindings/")]
+pub enum YTAState {
+    Idle,
+    Waiting(Option<DateTime<Utc>>),
+    Recording,
+    Muxing,
+    Finished,
+    AlreadyProcessed,
+    Ended,
+    Interrupted,
+    /// The stream can only be watched when logged in, e.g. because it's
+    /// members-only, and no valid cookies were given.
+    LoginRequired,
+    Errored,
+}
+
+lazy_static! {
+    // Not any "sign in to", which also starts YouTube's bot check
+    static ref LOGIN_REQUIRED_RE: Regex = Regex::new(concat!(
+        r"(?i)members[ -]only|join this channel|login required|requires login",
+        r"|sign in to confirm your age",
+    ))
+    .expect("Failed to compile regex for detecting login errors");
+}
+
+fn strip_ansi(s: &str) -> String {
+    lazy_static! {
+        static ref RE: Regex = Regex::new(concat!(
+            r"[\u001B\u009B][[\\]()#;?]*",
+            r"(?:(?:(?:[a-zA-Z\\d]*(?:;[a-zA-Z\\d]*)*)?\u0007)|",
+            r"(?:(?:\\d{1,4}(?:;\\d{0,4})*)?[\\dA-PRZcf-ntqry=><~]))",
+        ))
+        .expect("Failed to compile ANSI stripping regex");
+    }
+    let stripped = RE.replace_all(s, "").to_string();
+    stripped
+        .strip_suffix("\u{001b}[K")
+        .unwrap_or(&stripped)
+        .to_string()
+}
+
+impl YTAStatus {
+    pub fn new() -> Self {
+        Self {
+            version: None,
+            state: YTAState::Idle,
+            last_output: None,
+            last_update: chrono::Utc::now(),
+            video_fragments: None,
+            audio_fragments: None,
+            total_size: None,
+            video_quality: None,
+            mode: RecordingMode::Full,
+            vod: false,
+            progress: None,
+            output_file: None,
+        }
+    }
+
+    pub fn state(&self) -> &YTAState {
+        &self.state
+    }
+
+    pub fn last_update(&self) -> DateTime<Utc> {
+        self.last_update
+    }
+
+    pub fn video_fragments(&self) -> Option<u32> {
+        self.video_fragments
+    }
+
+    pub fn audio_fragments(&self) -> Option<u32> {
+        self.audio_fragments
+    }
+
+    pub fn total_size(&self) -> Option<&str> {
+        self.total_size.as_deref()
+    }
+
+    pub fn video_quality(&self) -> Option<&str> {
+        self.video_quality.as_deref()
+    }
+
+    /// parse_line parses a line of output from the ytarchive process.
+    ///
+    /// Sample output:
+    ///
+    ///   ytarchive 0.3.1-15663af
+    ///   Stream starts at 2022-03-14T14:00:00+00:00 in 11075 seconds. Waiting for this time to elapse...
+    ///   Stream is 30 seconds late...
+    ///   Selected quality: 1080p60 (h264)
+    ///   Video Fragments: 1215; Audio Fragments: 1215; Total Downloaded: 133.12MiB
+    ///   Download Finished
+    ///   Muxing final file...
+    ///   Final file: /path/to/output.mp4
+    pub fn parse_line(&mut self, line: &str) {
+        self.last_output = Some(line.to_string());
+        self.last_update = chrono::Utc::now();
+
+        if line.starts_with("Video Fragments: ") {
+            self.state = YTAState::Recording;
+            let mut parts = line.split(';').map(|s| s.split(':').nth(1).unwrap_or(""));
+            if let Some(x) = parts.next() {
+                self.video_fragments = x.trim().parse().ok();
+            };
+            if let Some(x) = parts.next() {
+                self.audio_fragments = x.trim().parse().ok();
+            };
+            if let Some(x) = parts.next() {
+                self.total_size = Some(strip_ansi(x.trim()));
+            };
+            return;
+        } else if line.starts_with("Audio Fragments: ") {
+            self.state = YTAState::Recording;
+            let mut parts = line.split(';').map(|s| s.split(':').nth(1).unwrap_or(""));
+            if let Some(x) = parts.next() {
+                self.audio_fragments = x.trim().parse().ok();
+            };
+            if let Some(x) = parts.next() {
+                self.total_size = Some(strip_ansi(x.trim()));
+            };
+            return;
+        }
+
+        // New versions of ytarchive prepend a timestamp to the output
+        // Sample line
+        // 2024/04/16 16:25:31
+        lazy_static! {
+            static ref TIMESTAMP_RE: Regex = Regex::new(r"^\d{4}/\d{2}/\d{2} \d{2}:\d{2}:\d{2}")
+                .expect("Failed to compile regex for detecting yta output timestamp");
+        }
+        let line = if line.len() > 20 && TIMESTAMP_RE.is_match(line) {
+            line[20..].trim()
+        } else {
+            line
+        };
+
+        if self.version == None && line.starts_with("ytarchive ") {
+            self.version = Some(strip_ansi(&line[10..]));
+        } else if self.video_quality == None && line.starts_with("Selected quality: ") {
+            self.video_quality = Some(strip_ansi(&line[18..]));
+        } else if line.starts_with("Stream starts at ") {
+            let date = DateTime::parse_from_rfc3339(&line[17..42])
+                .ok()
+                .map(|d| d.into());
+            self.state = YTAState::Waiting(date);
+        } else if line.starts_with("Stream is ") || line.starts_with("Waiting for stream") {
+            self.state = YTAState::Waiting(None);
+        } else if line.starts_with("Muxing final file") {
+            self.state = YTAState::Muxing;
+        } else if line.starts_with("Livestream has been processed") {
+            self.state = YTAState::AlreadyProcessed;
+        } else if line.starts_with("Livestream has ended and is being processed")
+            || line.contains("use yt-dlp to download it.")
+        {
+            self.state = YTAState::Ended;
+        } else if line.starts_with("Final file: ") {
+            self.state = YTAState::Finished;
+            self.output_file = Some(strip_ansi(&line[12..]));
+        } else if line.contains("User Interrupt") {
+            self.state = YTAState::Interrupted;
+        } else if !line.starts_with("Video Title: ")
+            && !line.starts_with("Channel: ")
+            && LOGIN_REQUIRED_RE.is_match(line)
+        {
+            self.state = YTAState::LoginRequired;
+        } else if line.contains("Error retrieving player response")
+            || line.contains("unable to retrieve")
+            || line.contains("error writing the muxcmd file")
+            || line.contains("Something must have gone wrong with ffmpeg")
+            || line.contains("At least one error occurred")
+        {
+            self.state = YTAState::Errored;
+        } else if line.trim().is_empty()
+            || line.contains("Loaded cookie file")
+            || line.starts_with("Video Title: ")
+            || line.starts_with("Channel: ")
+            || line.starts_with("Waiting for this time to elapse")
+            || line.starts_with("Download Finished")
+        {
+            // Ignore
+        } else {
+            warn!("Unknown ytarchive output: {}", line);
+        }
+    }
+
+    /// parse_vod_line parses a line of output from the yt-dlp process, which
+    /// only prints its progress, errors and the final file.
+    ///
+    /// Sample output:
+    ///
+    ///   [download]  12.3% of ~ 1.23GiB at  3.45MiB/s ETA 05:00 (frag 10/100)
+    ///   [download] 100% of  123.45MiB in 00:00:10 at 10.00MiB/s
+    ///   ERROR: [youtube] abc: Join this channel to get access to members-only content
+    ///   Final file: /path/to/output.mp4
+    pub fn parse_vod_line(&mut self, line: &str) {
+        self.last_output = Some(line.to_string());
+        self.last_update = chrono::Utc::now();
+
+        lazy_static! {
+            static ref PROGRESS_RE: Regex =
+                Regex::new(r"^\[download\]\s+([\d.]+)% of\s+~?\s*(\S+)")
+                    .expect("Failed to compile regex for yt-dlp progress");
+        }
+
+        if let Some(captures) = PROGRESS_RE.captures(line) {
+            self.state = YTAState::Recording;
+            self.progress = captures[1].parse().ok();
+            self.total_size = Some(captures[2].to_string());
+        } else if let Some(file) = line.strip_prefix("Final file: ") {
+            self.state = YTAState::Finished;
+            self.output_file = Some(file.to_string());
+        } else if line.starts_with("ERROR:") {
+            self.state = if LOGIN_REQUIRED_RE.is_match(line) {
+                YTAState::LoginRequired
+            } else {
+                YTAState::Errored
+            };
+        } else if !line.trim().is_empty() {
+            debug!("yt-dlp output: {}", line);
+        }
+    }
+}
+
+/// Creates an empty directory for a test.
+#[cfg(test)]
+pub(crate) fn test_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("hoshinova-{}-{}", name, std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        history::CompletedRecording, should_defer, take_due, test_dir, ytarchive_args, ytdlp_args,
+        Deduplication, SpawnTask, YTAState, YTAStatus, YTArchive,
+    };
+    use crate::config::{
+        Config, DuplicateMode, RecorderOverrides, RecordingMode, VodConfig, YtarchiveConfig,
+    };
+    use crate::module::{DuplicateReason, Message, Module, Task};
+    use crate::msgbus::MessageBus;
+    use chrono::{Duration, Utc};
+    use std::sync::Arc;
+    use tokio::sync::RwLock;
+
+    #[tokio::test]
+    async fn test_schedule() {
+        let mut bus = MessageBus::<Message>::new(10);
+        let tx = bus.add_tx();
+        let now = Utc::now();
+        let spawn = |video_id: &str, lead: Option<&str>, starts_in: Option<Duration>| {
+            let mut cfg: Config =
+                toml::from_str(include_str!("../../../config.example.toml")).unwrap();
+            cfg.ytarchive.schedule_lead_time = lead.map(|l| humantime::parse_duration(l).unwrap());
+            SpawnTask {
+                task: Task {
+                    title: "Karaoke".into(),
+                    video_id: video_id.into(),
+                    video_picture: "".into(),
+                    channel_name: "Moona".into(),
+                    channel_id: "UCP0BspO_AMEe3aQqqpo89Dg".into(),
+                    channel_picture: None,
+                    output_directory: "".into(),
+                    scheduled_start: starts_in.map(|d| now + d),
+                    recorder: Default::default(),
+                    source_channel_id: None,
+                },
+                cfg,
+                tx: tx.clone(),
+            }
+        };
+        let schedule = RwLock::new(vec![]);
+        let schedule_ref = &schedule;
+        let scheduled =
+            |task| async move { YTArchive::schedule(schedule_ref, task).await.is_none() };
+
+        // Spawned right away without a lead time, a start time, or if the
+        // stream starts within the lead time
+        assert!(!scheduled(spawn("a", None, Some(Duration::days(2)))).await);
+        assert!(!scheduled(spawn("b", Some("15m"), None)).await);
+        assert!(!scheduled(spawn("c", Some("15m"), Some(Duration::minutes(10)))).await);
+        assert!(!scheduled(spawn("d", Some("15m"), Some(-Duration::hours(1)))).await);
+
+        // Held back until the lead time before the start
+        assert!(scheduled(spawn("e", Some("15m"), Some(Duration::hours(1)))).await);
+        assert!(scheduled(spawn("f", Some("1h"), Some(Duration::days(1)))).await);
+        let mut schedule = schedule.into_inner();
+        assert_eq!(schedule.len(), 2);
+        assert_eq!(schedule[0].0.spawn_at, now + Duration::minutes(45));
+        assert_eq!(schedule[1].0.spawn_at, now + Duration::hours(23));
+
+        assert!(take_due(&mut schedule, now).is_empty());
+        let due = take_due(&mut schedule, now + Duration::hours(1));
+        assert_eq!(due.len(), 1);
+        assert_eq!(due[0].task.video_id, "e");
+        assert_eq!(schedule.len(), 1);
+        assert_eq!(schedule[0].1.task.video_id, "f");
+    }
+
+    #[test]
+    fn test_should_defer() {
+        let now = Utc::now();
+        let lead = Some(std::time::Duration::from_secs(15 * 60));
+        assert!(should_defer(now + Duration::hours(1), lead, now));
+        assert!(!should_defer(now + Duration::minutes(16), lead, now));
+        assert!(!should_defer(now + Duration::minutes(10), lead, now));
+        assert!(!should_defer(now + Duration::hours(1), None, now));
+    }
+
+    #[tokio::test]
+    async fn test_deduplicate() {
+        let dir = test_dir("deduplicate");
+        let dir_of = |name: &str| dir.join(name).to_string_lossy().into_owned();
+        let config: Config = toml::from_str(include_str!("../../../config.example.toml")).unwrap();
+        let yta = YTArchive::new(Arc::new(RwLock::new(config)));
+        let task = |video_id: &str, output_directory: String| Task {
+            title: "Karaoke".into(),
+            video_id: video_id.into(),
+            video_picture: "".into(),
+            channel_name: "Moona".into(),
+            channel_id: "UCP0BspO_AMEe3aQqqpo89Dg".into(),
+            channel_picture: None,
+            output_directory,
+            scheduled_start: None,
+            recorder: Default::default(),
+            source_channel_id: None,
+        };
+        let yta = &yta;
+        let dedup = |task: Task| async move { yta.deduplicate(&task).await };
+
+        // New videos are recorded
+        assert_eq!(dedup(task("live", dir_of("moona"))).await, None);
+
+        // Videos being recorded are placed into the other directories after
+        yta.active_ids
+            .write()
+            .await
+            .insert("live".into(), vec![dir_of("moona")]);
+        assert_eq!(
+            dedup(task("live", dir_of("moona"))).await,
+            Some(Deduplication::Skip(DuplicateReason::Active))
+        );
+        assert_eq!(
+            dedup(task("live", dir_of("collabs"))).await,
+            Some(Deduplication::Skip(DuplicateReason::Merged))
+        );
+        assert_eq!(
+            yta.active_ids.read().await["live"],
+            [dir_of("moona"), dir_of("collabs")]
+        );
+
+        // Finished recordings are linked into other directories
+        let src = dir.join("moona").join("Karaoke.mp4");
+        std::fs::create_dir_all(src.parent().unwrap()).unwrap();
+        std::fs::write(&src, "video").unwrap();
+        yta.history.write().await.insert(CompletedRecording {
+            video_id: "done".into(),
+            title: "Karaoke".into(),
+            channel_name: "Moona".into(),
+            finished_at: chrono::Utc::now(),
+            output_files: vec![src.to_string_lossy().into_owned()],
+        });
+        assert_eq!(
+            dedup(task("done", dir_of("moona"))).await,
+            Some(Deduplication::Skip(DuplicateReason::Recorded))
+        );
+        assert_eq!(
+            dedup(task("done", dir_of("collabs"))).await,
+            Some(Deduplication::Link(src.clone()))
+        );
+        // While the link is being made
+        assert_eq!(
+            dedup(task("done", dir_of("collabs"))).await,
+            Some(Deduplication::Skip(DuplicateReason::Recorded))
+        );
+
+        let mut bus = MessageBus::<Message>::new(10);
+        YTArchive::link(
+            yta.history.clone(),
+            yta.linking.clone(),
+            src.clone(),
+            task("done", dir_of("collabs")),
+            DuplicateMode::Hardlink,
+            bus.add_tx(),
+        )
+        .await;
+        let linked = dir.join("collabs").join("Karaoke.mp4");
+        assert_eq!(std::fs::read_to_string(&linked).unwrap(), "video");
+        assert!(yta.linking.read().await.is_empty());
+        assert_eq!(
+            dedup(task("done", dir_of("collabs"))).await,
+            Some(Deduplication::Skip(DuplicateReason::Recorded))
+        );
+
+        // Recordings that were deleted are recorded again
+        std::fs::remove_file(&src).unwrap();
+        std::fs::remove_file(&linked).unwrap();
+        assert_eq!(dedup(task("done", dir_of("pekora"))).await, None);
+
+        // Unless post-processing removed them, e.g. after uploading them
+        {
+            let mut history = yta.history.write().await;
+            for file in [&src, &linked] {
+                let file = file.to_string_lossy();
+                assert!(history.relocate("done", &file, None));
+            }
+        }
+        assert_eq!(
+            dedup(task("done", dir_of("pekora"))).await,
+            Some(Deduplication::Skip(DuplicateReason::Recorded))
+        );
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_parse_login_required() {
+        let mut status = YTAStatus::new();
+        status.parse_line("Video Title: Members only karaoke");
+        assert_eq!(status.state, YTAState::Idle);
+        status.parse_line(
+            "2024/04/16 16:25:31 ERROR: This video is members-only content. Provide cookies.",
+        );
+        assert_eq!(status.state, YTAState::LoginRequired);
+
+        // Cookies don't help against the bot check
+        let mut status = YTAStatus::new();
+        status.parse_line("ERROR: Sign in to confirm you're not a bot");
+        assert_ne!(status.state, YTAState::LoginRequired);
+        let mut status = YTAStatus::new();
+        status.parse_vod_line(
+            "ERROR: [youtube] abc: Sign in to confirm you’re not a bot. Use --cookies-from-browser or --cookies for the authentication.",
+        );
+        assert_eq!(status.state, YTAState::Errored);
+        status.parse_vod_line(
+            "ERROR: [youtube] abc: Sign in to confirm your age. This video may be inappropriate for some users.",
+        );
+        assert_eq!(status.state, YTAState::LoginRequired);
+        status.parse_vod_line(
+            "ERROR: [youtube] abc: Join this channel to get access to members-only content like this video",
+        );
+        assert_eq!(status.state, YTAState::LoginRequired);
+    }
+
+    #[test]
+    fn test_parse_vod_line() {
+        let mut status = YTAStatus::new();
+        status.parse_vod_line(
+            "[download]  12.3% of ~  1.23GiB at    3.45MiB/s ETA 05:00 (frag 10/100)",
+        );
+        assert_eq!(status.state, YTAState::Recording);
+        assert_eq!(status.progress, Some(12.3));
+        assert_eq!(status.total_size.as_deref(), Some("1.23GiB"));
+        status.parse_vod_line("[download] 100% of  123.45MiB in 00:00:10 at 10.00MiB/s");
+        assert_eq!(status.progress, Some(100.0));
+        assert_eq!(status.total_size.as_deref(), Some("123.45MiB"));
+        status.parse_vod_line("Final file: /temp/Karaoke [abc].mp4");
+        assert_eq!(status.state, YTAState::Finished);
+        assert_eq!(
+            status.output_file.as_deref(),
+            Some("/temp/Karaoke [abc].mp4")
+        );
+
+        let mut status = YTAStatus::new();
+        status.parse_vod_line(
+            "ERROR: [youtube] abc: Join this channel to get access to members-only content",
+        );
+        assert_eq!(status.state, YTAState::LoginRequired);
+        status.parse_vod_line("ERROR: [youtube] abc: Video unavailable");
+        assert_eq!(status.state, YTAState::Errored);
+    }
+
+    #[test]
+    fn test_ytdlp_args() {
+        let vod = VodConfig {
+            executable_path: "yt-dlp".into(),
+            args: vec!["--embed-thumbnail".into()],
+        };
+        let tail = [
+            "--newline",
+            "--progress",
+            "--print",
+            "after_move:Final file: %(filepath)s",
+            "https://www.youtube.com/watch?v=abc",
+        ];
+
+        let args = ytdlp_args(&vod, &RecordingMode::Full, Some("/cookies.txt"), "abc");
+        assert_eq!(
+            args,
+            [
+                &["--embed-thumbnail", "--cookies", "/cookies.txt"][..],
+                &tail
+            ]
+            .concat()
+        );
+
+        let args = ytdlp_args(&vod, &RecordingMode::AudioOnly, None, "abc");
+        assert_eq!(
+            args,
+            [&["--embed-thumbnail", "--format", "bestaudio"][..], &tail].concat()
+        );
+
+        let vod = VodConfig {
+            executable_path: "yt-dlp".into(),
+            args: vec!["-f".into(), "299+140".into()],
+        };
+        let args = ytdlp_args(&vod, &RecordingMode::VideoOnly, None, "abc");
+        assert_eq!(args, [&["-f", "299+140"][..], &tail].concat());
+    }
+
+    #[test]
+    fn test_ytarchive_args() {
+        let cfg: YtarchiveConfig = toml::from_str(
+            r#"
+            executable_path = "ytarchive"
+            working_directory = "temp"
+            args = ["--vp9", "--thumbnail", "--threads", "4"]
+            quality = "best"
+            delay_start = "1s"
+            "#,
+        )
+        .unwrap();
+
+        let args = ytarchive_args(&cfg, &RecorderOverrides::default(), None, "abc");
+        assert_eq!(
+            args,
+            [
+                "--vp9",
+                "--thumbnail",
+                "--threads",
+                "4",
+                "--wait",
+                "https://youtu.be/abc",
+                "best"
+            ]
+        );
+
+        let overrides = RecorderOverrides {
+            quality: Some("audio_only".into()),
+            args: vec!["--no-frag-files".into()],
+            threads: Some(8),
+            mode: None,
+            output_template: None,
+        };
+        let args = ytarchive_args(&cfg, &overrides, Some("/cookies.txt"), "abc");
+        assert_eq!(
+            args,
+            [
+                "--vp9",
+                "--thumbnail",
+                "--threads",
+                "8",
+                "--no-frag-files",
+                "--wait",
+                "--cookies",
+                "/cookies.txt",
+                "https://youtu.be/abc",
+                "audio_only"
+            ]
+        );
+
+        // -t is --thumbnail, not --threads
+        let cfg = YtarchiveConfig {
+            args: vec!["-t".into(), "--vp9".into(), "--threads=2".into()],
+            ..cfg
+        };
+        let overrides = RecorderOverrides {
+            threads: Some(8),
+            ..Default::default()
+        };
+        let args = ytarchive_args(&cfg, &overrides, None, "abc");
+        assert_eq!(
+            args,
+            [
+                "-t",
+                "--vp9",
+                "--threads",
+                "8",
+                "--wait",
+                "https://youtu.be/abc",
+                "best"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_ytarchive_args_mode() {
+        let cfg: YtarchiveConfig = toml::from_str(
+            r#"
+            executable_path = "ytarchive"
+            working_directory = "temp"
+            args = ["--wait"]
+            quality = "1080p60/best"
+            delay_start = "1s"
+            "#,
+        )
+        .unwrap();
+        let args = |mode| {
+            let overrides = RecorderOverrides {
+                mode: Some(mode),
+                ..Default::default()
+            };
+            ytarchive_args(&cfg, &overrides, None, "abc")
+        };
+
+        assert_eq!(
+            args(RecordingMode::Full),
+            ["--wait", "https://youtu.be/abc", "1080p60/best"]
+        );
+        assert_eq!(
+            args(RecordingMode::AudioOnly),
+            ["--wait", "https://youtu.be/abc", "audio_only"]
+        );
+        assert_eq!(
+            args(RecordingMode::VideoOnly),
+            [
+                "--wait",
+                "--video-only",
+                "https://youtu.be/abc",
+                "1080p60/best"
+            ]
+        );
+        assert_eq!(
+            args(RecordingMode::Ladder(vec!["720p60".into(), "480p".into()])),
+            ["--wait", "https://youtu.be/abc", "720p60/480p/best"]
+        );
+    }
+}
@@ -1,22 +1,52 @@
-use super::{Message, Module, Task};
-use crate::{config, msgbus::BusTx, youtube, APP_USER_AGENT};
+use super::{Message, Module, SystemEvent, SystemEventKind, Task};
+use crate::{
+    config, filter,
+    msgbus::BusTx,
+    youtube::{self, YouTube},
+};
 use anyhow::{Context, Result};
 use async_trait::async_trait;
 use futures::stream::{self, Stream, StreamExt};
-use reqwest::Client;
 use serde::Deserialize;
 use std::{
-    collections::HashSet,
+    collections::{HashMap, HashSet},
     sync::{Arc, Mutex},
-    time::Duration,
 };
 use tokio::sync::{mpsc, RwLock};
 
 pub struct RSS {
     config: Arc<RwLock<config::Config>>,
-    client: Client,
+    youtube: YouTube,
+    /// Video details fetched for filters that need more than the RSS feed
+    /// provides, keyed by video ID.
+    details: Mutex<HashMap<String, (chrono::DateTime<chrono::Utc>, filter::Video)>>,
+    /// Pairs of (video ID, channel ID) already queued because the video
+    /// mentions the channel, along with when the video was last seen in a
+    /// feed.
+    mentioned: Mutex<HashMap<(String, String), chrono::DateTime<chrono::Utc>>>,
+    /// Number of consecutive failed polls, keyed by channel ID.
+    failures: Mutex<HashMap<String, u32>>,
+    /// System events to send once the current poll is done.
+    events: Mutex<Vec<SystemEvent>>,
+}
+
+/// A feed to be scraped.
+enum Source {
+    /// A configured channel, whose videos are matched against its filters.
+    Channel(Box<config::ChannelConfig>),
+    /// A channel whose videos are only checked for mentions.
+    WatchOnly(String),
 }
 
+/// A configured channel along with the regex to find mentions of it.
+struct Mentionable {
+    channel: config::ChannelConfig,
+    regex: regex::Regex,
+}
+
+/// How long fetched video details are reused before being fetched again.
+const DETAILS_TTL_HOURS: i64 = 1;
+
 #[derive(Deserialize)]
 struct RSSFeed {
     #[serde(rename = "entry", default)]
@@ -51,18 +81,40 @@ struct Thumbnail {
     url: String,
 }
 
+impl Source {
+    /// Returns the ID and the name of the channel.
+    fn describe(&self) -> (String, String) {
+        match self {
+            Source::Channel(channel) => (channel.id.clone(), channel.name.clone()),
+            Source::WatchOnly(id) => (id.clone(), id.clone()),
+        }
+    }
+}
+
 impl RSS {
     async fn run_one(
         &self,
         scraped: Arc<Mutex<HashSet<String>>>,
-        channel: config::ChannelConfig,
-    ) -> Result<impl Stream<Item = Task>> {
-        debug!("Fetching RSS for {}", channel.name);
+        mentionables: Arc<Vec<Mentionable>>,
+        source: Source,
+    ) -> Result<Vec<Task>> {
+        let (channel_id, channel) = match source {
+            Source::Channel(channel) => (channel.id.clone(), Some(*channel)),
+            Source::WatchOnly(channel_id) => (channel_id, None),
+        };
+        debug!(
+            "Fetching RSS for {}",
+            channel.as_ref().map(|c| &c.name).unwrap_or(&channel_id)
+        );
 
         // Get config
-        let max_age =
-            chrono::Duration::from_std(self.config.read().await.scraper.rss.ignore_older_than)
+        let (max_age, watches, scheduling) = {
+            let cfg = self.config.read().await;
+            let max_age = chrono::Duration::from_std(cfg.scraper.rss.ignore_older_than)
                 .context("Failed to convert ignore_older_than to chrono::Duration")?;
+            let scheduling = cfg.ytarchive.schedule_lead_time.is_some();
+            (max_age, cfg.watch.clone(), scheduling)
+        };
         debug!(
             "Ignoring videos older than {}",
             max_age
@@ -75,64 +127,227 @@ impl RSS {
         // Fetch the RSS feed
         let url = format!(
             "https://www.youtube.com/feeds/videos.xml?channel_id={}",
-            channel.id
+            channel_id
         );
         let res = self
-            .client
+            .youtube
             .get(&url)
-            .send()
             .await
             .context("Failed to fetch RSS feed")?;
         let feed: RSSFeed =
             quick_xml::de::from_slice(&res.bytes().await.context("Failed to read RSS feed body")?)
                 .context("Failed to parse RSS feed")?;
 
-        // Find matching videos
-        let tasks: Vec<Task> = feed
+        // Drop videos that are too old
+        let entries: Vec<FeedEntry> = feed
             .entries
-            .iter()
-            .filter_map(move |entry| {
-                let mut scraped = scraped.lock().unwrap();
-
-                if scraped.contains(&entry.video_id) {
-                    // Skip if video has already been scraped
-                    debug!("Skipping {}: already scraped", entry.video_id);
-                    return None;
-                } else if entry.updated < chrono::Utc::now() - max_age {
-                    // Or if the video is too old
+            .into_iter()
+            .filter(|entry| {
+                let too_old = entry.updated < chrono::Utc::now() - max_age;
+                if too_old {
                     debug!(
                         "Skipping {}: too old ({} < {})",
                         entry.video_id,
                         entry.updated,
                         chrono::Utc::now() - max_age
                     );
-                    return None;
-                } else if !channel.filters.iter().any(|filter| {
-                    filter.is_match(&entry.title)
-                        || (channel.match_description && filter.is_match(&entry.group.description))
-                }) {
+                }
+                !too_old
+            })
+            .collect();
+
+        // Find videos mentioning other channels
+        let mut tasks = self
+            .find_mentions(&mentionables, &entries, max_age, scheduling)
+            .await;
+        let channel = match channel {
+            Some(channel) => channel,
+            None => return Ok(tasks),
+        };
+
+        // Find matching videos
+        for entry in entries {
+            if scraped.lock().unwrap().contains(&entry.video_id) {
+                // Skip if video has already been scraped
+                debug!("Skipping {}: already scraped", entry.video_id);
+                continue;
+            }
+
+            // Check the filters using the RSS entry, and only fetch the video
+            // details if they're needed to decide
+            let video = filter::Video {
+                title: entry.title.clone(),
+                description: Some(entry.group.description.clone()),
+                ..Default::default()
+            };
+            let outpath = match route(&channel, &watches, &video, false) {
+                Some(outpath) => outpath,
+                None => match self.fetch_details(&entry.video_id).await {
+                    Ok(video) => route(&channel, &watches, &video, true).flatten(),
+                    Err(e) => {
+                        warn!("Failed to fetch details for {}: {:?}", entry.video_id, e);
+                        continue;
+                    }
+                },
+            };
+            let outpath = match outpath {
+                Some(outpath) => outpath,
+                None => {
                     // Or if the video doesn't match the filters
                     debug!("Skipping {}: doesn't match filters", entry.video_id);
-                    return None;
+                    continue;
                 }
+            };
 
-                // Add to scraped set
-                scraped.insert(entry.video_id.clone());
-
-                // Return the task
-                Some(Task {
-                    title: entry.title.to_owned(),
-                    video_id: entry.video_id.to_owned(),
-                    video_picture: entry.group.thumbnail.url.to_owned(),
-                    channel_name: entry.author.name.to_owned(),
-                    channel_id: entry.channel_id.to_owned(),
-                    channel_picture: channel.picture_url.clone(),
-                    output_directory: channel.outpath.clone(),
-                })
-            })
-            .collect();
+            // Add to scraped set
+            scraped.lock().unwrap().insert(entry.video_id.clone());
+
+            // Find out when the stream starts, so the recorder can schedule it
+            let mut scheduled_start = None;
+            if scheduling {
+                match self.fetch_details(&entry.video_id).await {
+                    Ok(video) => scheduled_start = video.scheduled_start,
+                    Err(e) => warn!("Failed to fetch details for {}: {:?}", entry.video_id, e),
+                }
+            }
+
+            tasks.push(Task {
+                title: entry.title,
+                video_id: entry.video_id,
+                video_picture: entry.group.thumbnail.url,
+                channel_name: entry.author.name,
+                channel_id: entry.channel_id,
+                channel_picture: channel.picture_url.clone(),
+                output_directory: outpath,
+                scheduled_start,
+                recorder: channel.recorder.clone(),
+                source_channel_id: None,
+            });
+        }
+
+        Ok(tasks)
+    }
+
+    /// Returns tasks for videos that mention a configured channel other than
+    /// the one that uploaded them, unless they match the `exclude` filters of
+    /// the mentioned channel.
+    async fn find_mentions(
+        &self,
+        mentionables: &[Mentionable],
+        entries: &[FeedEntry],
+        max_age: chrono::Duration,
+        scheduling: bool,
+    ) -> Vec<Task> {
+        // Forget videos that have dropped out of the feeds
+        let candidates = {
+            let now = chrono::Utc::now();
+            let mut mentioned = self.mentioned.lock().unwrap();
+            mentioned.retain(|_, seen| *seen > now - max_age);
+
+            let mut candidates = vec![];
+            for entry in entries {
+                for mentionable in mentionables {
+                    let channel = &mentionable.channel;
+                    let key = (entry.video_id.clone(), channel.id.clone());
+                    if let Some(seen) = mentioned.get_mut(&key) {
+                        *seen = now;
+                        continue;
+                    }
+                    if channel.id == entry.channel_id
+                        || !(mentionable.regex.is_match(&entry.title)
+                            || mentionable.regex.is_match(&entry.group.description))
+                    {
+                        continue;
+                    }
+                    candidates.push((entry, channel));
+                }
+            }
+            candidates
+        };
+
+        let mut tasks = vec![];
+        for (entry, channel) in candidates {
+            // Check the exclude filters using the RSS entry, and only fetch
+            // the video details if they're needed to decide
+            let video = filter::Video {
+                title: entry.title.clone(),
+                description: Some(entry.group.description.clone()),
+                ..Default::default()
+            };
+            let details = match channel.excludes(&video) {
+                Some(false) if !scheduling => None,
+                Some(true) => {
+                    debug!(
+                        "Skipping mention of {} in {}: excluded",
+                        channel.name, entry.video_id
+                    );
+                    continue;
+                }
+                _ => match self.fetch_details(&entry.video_id).await {
+                    Ok(video) => Some(video),
+                    Err(e) => {
+                        warn!("Failed to fetch details for {}: {:?}", entry.video_id, e);
+                        continue;
+                    }
+                },
+            };
+            if let Some(true) = details.as_ref().and_then(|video| channel.excludes(video)) {
+                debug!(
+                    "Skipping mention of {} in {}: excluded",
+                    channel.name, entry.video_id
+                );
+                continue;
+            }
+
+            info!(
+                "Found mention of {} in {} ({})",
+                channel.name, entry.video_id, entry.author.name
+            );
+            self.mentioned.lock().unwrap().insert(
+                (entry.video_id.clone(), channel.id.clone()),
+                chrono::Utc::now(),
+            );
+            tasks.push(Task {
+                title: entry.title.clone(),
+                video_id: entry.video_id.clone(),
+                video_picture: entry.group.thumbnail.url.clone(),
+                channel_name: entry.author.name.clone(),
+                channel_id: entry.channel_id.clone(),
+                channel_picture: mentionables
+                    .iter()
+                    .find(|m| m.channel.id == entry.channel_id)
+                    .and_then(|m| m.channel.picture_url.clone()),
+                output_directory: channel.outpath.clone(),
+                scheduled_start: details.and_then(|video| video.scheduled_start),
+                recorder: channel.recorder.clone(),
+                source_channel_id: Some(channel.id.clone()),
+            });
+        }
+        tasks
+    }
+
+    /// Fetches the details of a video from its watch page, reusing recently
+    /// fetched results.
+    async fn fetch_details(&self, video_id: &str) -> Result<filter::Video> {
+        let now = chrono::Utc::now();
+        let ttl = chrono::Duration::hours(DETAILS_TTL_HOURS);
+        if let Some((fetched, video)) = self.details.lock().unwrap().get(video_id) {
+            if *fetched > now - ttl {
+                return Ok(video.clone());
+            }
+        }
+
+        debug!("Fetching details for {}", video_id);
+        let url = format!("https://www.youtube.com/watch?v={}", video_id);
+        let ipr = youtube::video::fetch_initial_player_response(&self.youtube, &url)
+            .await
+            .context("Failed to fetch video details")?;
+        let video = filter::Video::from(&ipr);
 
-        Ok(stream::iter(tasks))
+        let mut details = self.details.lock().unwrap();
+        details.retain(|_, (fetched, _)| *fetched > now - ttl);
+        details.insert(video_id.to_owned(), (now, video.clone()));
+        Ok(video)
     }
 
     async fn run_loop(
@@ -140,13 +355,77 @@ impl RSS {
         scraped: Arc<Mutex<HashSet<String>>>,
     ) -> impl Stream<Item = Task> + '_ {
         let config = self.config.read().await;
-        stream::iter(config.channel.clone())
-            .map(move |channel| self.run_one(scraped.clone(), channel))
+        let mut sources = config
+            .channel
+            .iter()
+            .map(|channel| Source::Channel(Box::new(channel.clone())))
+            .collect::<Vec<_>>();
+
+        // Prepare collab detection
+        let mut mentionables = vec![];
+        if let Some(collab) = &config.scraper.collab {
+            sources.extend(collab.watch_only.iter().cloned().map(Source::WatchOnly));
+            for channel in &config.channel {
+                match channel.mention_regex() {
+                    Ok(regex) => mentionables.push(Mentionable {
+                        channel: channel.clone(),
+                        regex,
+                    }),
+                    Err(e) => warn!("{:?}", e),
+                }
+            }
+        }
+        let mentionables = Arc::new(mentionables);
+        let threshold = config.monitor.scraper_failures;
+
+        stream::iter(sources)
+            .map(move |source| {
+                let (id, name) = source.describe();
+                let res = self.run_one(scraped.clone(), mentionables.clone(), source);
+                async move {
+                    let res = res.await;
+                    self.record_poll(&id, &name, &res, threshold);
+                    res
+                }
+            })
             .buffer_unordered(4)
             .filter_map(|one| async { one.map_err(|e| error!("Failed to run RSS: {:?}", e)).ok() })
+            .map(stream::iter)
             .flatten()
     }
 
+    /// Counts consecutive failed polls of a channel, and queues an event when
+    /// they reach the threshold, and again once the channel recovers.
+    fn record_poll(&self, id: &str, name: &str, res: &Result<Vec<Task>>, threshold: u32) {
+        let mut failures = self.failures.lock().unwrap();
+        let count = failures.entry(id.to_owned()).or_insert(0);
+        let event = match res {
+            Ok(_) => {
+                let failed = threshold > 0 && *count >= threshold;
+                let event = failed.then(|| {
+                    SystemEvent::new(
+                        SystemEventKind::ScraperRecovered,
+                        format!("Polling {} works again after {} failures", name, count),
+                    )
+                });
+                *count = 0;
+                event
+            }
+            Err(e) => {
+                *count += 1;
+                (*count == threshold).then(|| {
+                    SystemEvent::new(
+                        SystemEventKind::ScraperFailing,
+                        format!("Polling {} failed {} times in a row: {:#}", name, count, e),
+                    )
+                })
+            }
+        };
+        if let Some(event) = event {
+            self.events.lock().unwrap().push(event);
+        }
+    }
+
     async fn cache_picture_url(&self) -> Result<()> {
         let cfg = self.config.clone();
         let cfg: &mut config::Config = &mut *cfg.write().await;
@@ -156,7 +435,7 @@ impl RSS {
             }
 
             channel.picture_url = Some(
-                youtube::channel::fetch_picture_url(self.client.clone(), &channel.id)
+                youtube::channel::fetch_picture_url(&self.youtube, &channel.id)
                     .await
                     .context("Failed to fetch channel picture URL")?,
             );
@@ -165,15 +444,46 @@ impl RSS {
     }
 }
 
+/// Decides where a video should be saved, checking the channel's filters first
+/// and then the global watch rules in order. Returns `Some(None)` if nothing
+/// matches, or `None` if more details about the video are needed. Once all
+/// details are `complete`, unknown results are treated as not matching.
+fn route(
+    channel: &config::ChannelConfig,
+    watches: &[config::WatchConfig],
+    video: &filter::Video,
+    complete: bool,
+) -> Option<Option<String>> {
+    let rules = std::iter::once((channel.matches(video), &channel.outpath)).chain(
+        watches.iter().map(|watch| {
+            (
+                watch.matches(video),
+                watch.outpath.as_ref().unwrap_or(&channel.outpath),
+            )
+        }),
+    );
+
+    for (matched, outpath) in rules {
+        match matched {
+            Some(true) => return Some(Some(outpath.clone())),
+            None if !complete => return None,
+            _ => (),
+        }
+    }
+    Some(None)
+}
+
 #[async_trait]
 impl Module for RSS {
     fn new(config: Arc<RwLock<config::Config>>) -> Self {
-        let client = Client::builder()
-            .user_agent(APP_USER_AGENT)
-            .timeout(Duration::from_secs(30))
-            .build()
-            .expect("Failed to create client");
-        Self { config, client }
+        Self {
+            config,
+            youtube: youtube::shared().clone(),
+            details: Mutex::new(HashMap::new()),
+            mentioned: Mutex::new(HashMap::new()),
+            failures: Mutex::new(HashMap::new()),
+            events: Mutex::new(vec![]),
+        }
     }
 
     async fn run(&self, tx: &BusTx<Message>, rx: &mut mpsc::Receiver<Message>) -> Result<()> {
@@ -200,6 +510,16 @@ impl Module for RSS {
                 return Ok(());
             }
 
+            // Report channels that keep failing
+            let events = std::mem::take(&mut *self.events.lock().unwrap());
+            for event in events {
+                warn!("{}: {}", event.kind.title(), event.message);
+                if tx.send(Message::SystemEvent(event)).await.is_err() {
+                    debug!("Failed to send message to bus");
+                    return Ok(());
+                }
+            }
+
             // Determine when to wake up
             let wakeup = {
                 let cfg = self.config.read().await;
@@ -222,3 +542,89 @@ impl Module for RSS {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::route;
+    use crate::{
+        config::{ChannelConfig, WatchConfig},
+        filter,
+    };
+
+    fn video(title: &str, live: Option<bool>) -> filter::Video {
+        filter::Video {
+            title: title.into(),
+            live,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_route() {
+        let channel: ChannelConfig = toml::from_str(
+            r#"
+            id = "UCP0BspO_AMEe3aQqqpo89Dg"
+            name = "Moona"
+            filters = ["(?i)karaoke"]
+            outpath = "./videos/moona"
+            "#,
+        )
+        .unwrap();
+        let watches: Vec<WatchConfig> = vec![
+            toml::from_str(
+                r#"
+                name = "Singing"
+                include = ['live and "(?i)singing"']
+                outpath = "./videos/singing"
+                "#,
+            )
+            .unwrap(),
+            toml::from_str(
+                r#"
+                name = "Anniversary"
+                include = ['"(?i)anniversary"']
+                "#,
+            )
+            .unwrap(),
+        ];
+        let check = |video: &filter::Video, complete| route(&channel, &watches, video, complete);
+        let to = |outpath: &str| Some(Some(outpath.to_string()));
+
+        // Matched by the channel only
+        assert_eq!(check(&video("Karaoke", None), false), to("./videos/moona"));
+        // Matched by a watch rule only, into its outpath or the channel's
+        assert_eq!(
+            check(&video("Singing", Some(true)), false),
+            to("./videos/singing")
+        );
+        assert_eq!(
+            check(&video("3D Anniversary", None), false),
+            to("./videos/moona")
+        );
+        // Matched by both, the channel wins
+        assert_eq!(
+            check(&video("Singing karaoke", Some(true)), false),
+            to("./videos/moona")
+        );
+        // Matched by neither
+        assert_eq!(check(&video("Minecraft", None), false), Some(None));
+
+        // Whether it's live is needed, so the details have to be fetched
+        assert_eq!(check(&video("Singing", None), false), None);
+        assert_eq!(check(&video("Singing", None), true), Some(None));
+        assert_eq!(check(&video("Singing", Some(false)), true), Some(None));
+
+        // Recording every live stream always needs the details
+        let channel = ChannelConfig {
+            record_all_live: true,
+            ..channel.clone()
+        };
+        let check = |video: &filter::Video, complete| route(&channel, &[], video, complete);
+        assert_eq!(check(&video("Karaoke", None), false), None);
+        assert_eq!(
+            check(&video("Zatsudan", Some(true)), true),
+            to("./videos/moona")
+        );
+        assert_eq!(check(&video("Karaoke", Some(false)), true), Some(None));
+    }
+}
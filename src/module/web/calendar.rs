@@ -0,0 +1,136 @@
+use super::{ScheduleEntry, TaskWithStatus};
+use crate::module::recorder::YTAState;
+use chrono::{DateTime, Duration, Utc};
+
+/// Length of events whose end time is not known.
+const DEFAULT_EVENT_HOURS: i64 = 1;
+
+/// Renders upcoming streams, and optionally past recordings, as an iCalendar
+/// feed.
+pub fn render(upcoming: &[ScheduleEntry], past: &[TaskWithStatus]) -> String {
+    let now = Utc::now();
+    let mut lines = vec![
+        "BEGIN:VCALENDAR".to_string(),
+        "VERSION:2.0".to_string(),
+        format!("PRODID:-//{}//EN", crate::APP_NAME),
+        "CALSCALE:GREGORIAN".to_string(),
+        "X-WR-CALNAME:hoshinova".to_string(),
+    ];
+
+    for entry in upcoming {
+        let start = match entry.starts_at {
+            Some(start) => start,
+            None => continue,
+        };
+        let status = match entry.spawn_at {
+            Some(_) => "Scheduled",
+            None => "Waiting",
+        };
+        lines.extend(event(
+            &entry.task,
+            status,
+            now,
+            start,
+            start + Duration::hours(DEFAULT_EVENT_HOURS),
+        ));
+    }
+
+    for t in past {
+        let end = t.status.last_update();
+        let start = t.task.scheduled_start.unwrap_or(end);
+        let end = if end > start {
+            end
+        } else {
+            start + Duration::hours(DEFAULT_EVENT_HOURS)
+        };
+        let status = match t.status.state() {
+            YTAState::Finished => "Done",
+            YTAState::AlreadyProcessed => "Already processed",
+            YTAState::Ended => "Ended",
+            _ => "Failed",
+        };
+        lines.extend(event(&t.task, status, now, start, end));
+    }
+
+    lines.push("END:VCALENDAR".to_string());
+    lines
+        .iter()
+        .map(|line| fold(line))
+        .collect::<Vec<_>>()
+        .join("\r\n")
+        + "\r\n"
+}
+
+fn event(
+    task: &crate::module::Task,
+    status: &str,
+    now: DateTime<Utc>,
+    start: DateTime<Utc>,
+    end: DateTime<Utc>,
+) -> Vec<String> {
+    let url = format!("https://youtu.be/{}", task.video_id);
+    vec![
+        "BEGIN:VEVENT".to_string(),
+        format!("UID:{}@hoshinova", task.video_id),
+        format!("DTSTAMP:{}", timestamp(now)),
+        format!("DTSTART:{}", timestamp(start)),
+        format!("DTEND:{}", timestamp(end)),
+        format!("SUMMARY:{}", escape(&task.title)),
+        format!(
+            "DESCRIPTION:{}",
+            escape(&format!(
+                "Channel: {}\nStatus: {}\n{}",
+                task.channel_name, status, url
+            ))
+        ),
+        format!("URL:{}", url),
+        format!("CATEGORIES:{}", escape(&task.channel_name)),
+        "END:VEVENT".to_string(),
+    ]
+}
+
+fn timestamp(t: DateTime<Utc>) -> String {
+    t.format("%Y%m%dT%H%M%SZ").to_string()
+}
+
+/// Escapes a text value as per RFC 5545.
+fn escape(s: &str) -> String {
+    s.replace('\\', "\\\\")
+        .replace(';', "\\;")
+        .replace(',', "\\,")
+        .replace('\n', "\\n")
+}
+
+/// Folds a content line into lines of at most 75 octets, without splitting
+/// any UTF-8 character.
+fn fold(line: &str) -> String {
+    let mut folded = String::with_capacity(line.len());
+    let mut len = 0;
+    for c in line.chars() {
+        if len + c.len_utf8() > 75 {
+            folded.push_str("\r\n ");
+            // The leading space counts towards the line length
+            len = 1;
+        }
+        folded.push(c);
+        len += c.len_utf8();
+    }
+    folded
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{escape, fold};
+
+    #[test]
+    fn test_escape_and_fold() {
+        assert_eq!(escape("a, b; c\\d\ne"), "a\\, b\\; c\\\\d\\ne");
+        assert_eq!(fold("short"), "short");
+
+        let folded = fold(&"あ".repeat(40));
+        for line in folded.split("\r\n") {
+            assert!(line.len() <= 75);
+        }
+        assert_eq!(folded.replace("\r\n ", ""), "あ".repeat(40));
+    }
+}
@@ -1,19 +1,23 @@
-use super::TaskMap;
+use super::{DuplicateList, PostProcessList, ScheduleMap, TaskMap};
 use crate::{
-    config::Config,
-    module::{Message, Task},
+    config::{Config, RecorderOverrides},
+    filter::{self, Filter},
+    module::{
+        recorder::{disk, YTAState},
+        Message, SystemEvent, SystemEventKind, Task,
+    },
     msgbus::BusTx,
     youtube,
 };
 use actix_web::{
-    error::{ErrorBadRequest, ErrorInternalServerError},
+    error::{ErrorBadRequest, ErrorInternalServerError, ErrorNotFound},
     get, post, put,
     web::{self, Data},
     HttpResponse, Responder,
 };
-use anyhow::anyhow;
-use serde::Deserialize;
-use std::sync::Arc;
+use anyhow::{anyhow, Context};
+use serde::{Deserialize, Serialize};
+use std::{collections::HashMap, sync::Arc};
 use tokio::sync::RwLock;
 use ts_rs::TS;
 
@@ -25,11 +29,19 @@ struct StaticFiles;
 pub fn configure(cfg: &mut actix_web::web::ServiceConfig) {
     cfg.service(get_tasks)
         .service(post_task)
+        .service(get_duplicates)
+        .service(get_postprocess)
+        .service(get_schedule)
+        .service(get_calendar)
+        .service(get_disk)
         .service(get_version)
+        .service(get_youtube_stats)
         .service(get_config)
         .service(get_config_toml)
         .service(put_config_toml)
         .service(reload_config)
+        .service(test_filter)
+        .service(resolve_channel)
         .service(serve_static);
 }
 
@@ -44,64 +56,210 @@ async fn get_tasks(data: TaskMap) -> actix_web::Result<impl Responder> {
     ))
 }
 
+#[get("/api/duplicates")]
+async fn get_duplicates(data: DuplicateList) -> actix_web::Result<impl Responder> {
+    Ok(HttpResponse::Ok().json(data.read().await.to_owned()))
+}
+
+#[get("/api/postprocess")]
+async fn get_postprocess(data: PostProcessList) -> actix_web::Result<impl Responder> {
+    Ok(HttpResponse::Ok().json(data.read().await.to_owned()))
+}
+
+#[get("/api/disk")]
+async fn get_disk(config: Data<Arc<RwLock<Config>>>) -> actix_web::Result<impl Responder> {
+    let config = config.read().await.clone();
+    let usage = web::block(move || disk::usage(&config)).await?;
+    Ok(HttpResponse::Ok().json(usage))
+}
+
+#[get("/api/schedule")]
+async fn get_schedule(tasks: TaskMap, scheduled: ScheduleMap) -> actix_web::Result<impl Responder> {
+    Ok(HttpResponse::Ok().json(super::get_schedule(&tasks, &scheduled).await))
+}
+
+#[derive(Deserialize)]
+struct CalendarQuery {
+    /// Also include recordings that have finished or failed.
+    #[serde(default)]
+    past: bool,
+}
+
+#[get("/api/calendar.ics")]
+async fn get_calendar(
+    tasks: TaskMap,
+    scheduled: ScheduleMap,
+    query: web::Query<CalendarQuery>,
+) -> actix_web::Result<impl Responder> {
+    let upcoming = super::get_schedule(&tasks, &scheduled).await;
+    let mut past = vec![];
+    if query.past {
+        past = tasks
+            .read()
+            .await
+            .values()
+            .filter(|t| {
+                matches!(
+                    t.status.state(),
+                    YTAState::Finished
+                        | YTAState::AlreadyProcessed
+                        | YTAState::Ended
+                        | YTAState::Interrupted
+                        | YTAState::LoginRequired
+                        | YTAState::Errored
+                )
+            })
+            .cloned()
+            .collect();
+    }
+
+    Ok(HttpResponse::Ok()
+        .content_type("text/calendar; charset=utf-8")
+        .body(super::calendar::render(&upcoming, &past)))
+}
+
 #[derive(Deserialize, TS)]
 #[ts(export, export_to = "web/src/bindings/")]
 struct CreateTaskRequest {
+    /// A video, a playlist, or a channel to record the current stream of.
     video_url: String,
     output_directory: String,
+    /// Queue every video of a playlist, not only the live and upcoming ones.
+    #[serde(default)]
+    all_entries: bool,
+    /// Recorder settings for this task. Unset fields are taken from the
+    /// video's channel if it is configured, or the `ytarchive` config.
+    #[serde(flatten)]
+    recorder: RecorderOverrides,
 }
 
-#[post("/api/task")]
-async fn post_task(
-    tx: Data<BusTx<Message>>,
-    taskreq: web::Json<CreateTaskRequest>,
-) -> actix_web::Result<impl Responder> {
-    let taskreq = taskreq.into_inner();
-    let client = reqwest::Client::new();
-
-    // Make sure the video URL is valid
-    let url =
-        youtube::URL::parse(&taskreq.video_url).map_err(|e| ErrorBadRequest(format!("{:?}", e)))?;
-    let video_id = url
-        .video_id()
-        .ok_or(ErrorBadRequest(anyhow!("Not a video URL")))?;
-    let video_url = format!("https://www.youtube.com/watch?v={}", video_id);
-
-    // Fetch video details
-    let ipr = youtube::video::fetch_initial_player_response(client.clone(), &video_url)
-        .await
-        .map_err(|e| ErrorInternalServerError(format!("{:?}", e)))?;
+#[derive(Serialize, TS)]
+#[ts(export, export_to = "web/src/bindings/")]
+struct CreateTaskResponse {
+    /// IDs of the videos that were queued.
+    video_ids: Vec<String>,
+}
+
+/// Creates a task for a video from its details.
+async fn create_task(
+    youtube: &youtube::YouTube,
+    ipr: youtube::video::InitialPlayerResponse,
+    taskreq: &CreateTaskRequest,
+    channel_pictures: &mut HashMap<String, String>,
+) -> anyhow::Result<Task> {
+    let scheduled_start = ipr.start_time();
 
     // Get the best thumbnail
     let mut thumbs = ipr.video_details.thumbnail.thumbnails;
     thumbs.sort_by_key(|t| t.width);
     let best_thumb = thumbs.last().map(|t| t.url.clone()).unwrap_or("".into());
 
-    // Fetch the channel image
-    let channel_picture =
-        youtube::channel::fetch_picture_url(client, &ipr.video_details.channel_id)
-            .await
-            .map_err(|e| {
-                ErrorInternalServerError(anyhow!("Failed to fetch channel picture: {:?}", e))
-            })?;
+    // Fetch the channel image, once per channel
+    let channel_id = ipr.video_details.channel_id;
+    let channel_picture = match channel_pictures.get(&channel_id) {
+        Some(picture) => picture.clone(),
+        None => {
+            let picture = youtube::channel::fetch_picture_url(youtube, &channel_id)
+                .await
+                .context("Failed to fetch channel picture")?;
+            channel_pictures.insert(channel_id.clone(), picture.clone());
+            picture
+        }
+    };
 
-    // Create the task
-    let task = Task {
+    Ok(Task {
         title: ipr.video_details.title,
         video_id: ipr.video_details.video_id,
         video_picture: best_thumb,
         channel_name: ipr.video_details.author,
-        channel_id: ipr.video_details.channel_id,
+        channel_id,
         channel_picture: Some(channel_picture),
-        output_directory: taskreq.output_directory,
-    };
+        output_directory: taskreq.output_directory.clone(),
+        scheduled_start,
+        recorder: taskreq.recorder.clone(),
+        source_channel_id: None,
+    })
+}
 
-    // Broadcast it to the bus
-    tx.send(Message::ToRecord(task))
-        .await
-        .map_err(|e| ErrorInternalServerError(format!("{:?}", e)))?;
+#[post("/api/task")]
+async fn post_task(
+    tx: Data<BusTx<Message>>,
+    taskreq: web::Json<CreateTaskRequest>,
+) -> actix_web::Result<impl Responder> {
+    let taskreq = taskreq.into_inner();
+    let youtube = youtube::shared();
+
+    // Make sure the URL is valid, channels can also be given as a handle
+    let url = youtube::URL::parse(&taskreq.video_url);
+    let is_channel = youtube::channel::channel_page_url(&taskreq.video_url).is_ok();
+    if let (Err(e), false) = (&url, is_channel) {
+        return Err(ErrorBadRequest(format!("{:?}", e)));
+    }
+    let url = url.ok();
+
+    // Find the videos to queue. A channel is checked for its current stream
+    // right away, so its details don't need to be fetched again.
+    let mut streams = vec![];
+    let mut video_ids = vec![];
+    if let Some(video_id) = url.as_ref().and_then(|u| u.video_id()) {
+        video_ids.push(video_id);
+    } else if let Some(playlist_id) = url.as_ref().and_then(|u| u.playlist_id()) {
+        let entries = youtube::playlist::fetch_entries(youtube, &playlist_id)
+            .await
+            .map_err(|e| ErrorInternalServerError(format!("{:?}", e)))?;
+        video_ids.extend(
+            entries
+                .into_iter()
+                .filter(|e| taskreq.all_entries || e.is_live_or_upcoming())
+                .map(|e| e.video_id),
+        );
+    } else if is_channel {
+        let ipr = youtube::channel::fetch_live(youtube, &taskreq.video_url)
+            .await
+            .map_err(|e| ErrorInternalServerError(format!("{:?}", e)))?
+            .ok_or_else(|| ErrorNotFound("The channel is not live"))?;
+        streams.push(ipr);
+    } else {
+        return Err(ErrorBadRequest(
+            "Not a video, playlist or channel URL".to_string(),
+        ));
+    }
 
-    Ok(HttpResponse::Accepted().finish())
+    // Fetch the details of the videos
+    for video_id in &video_ids {
+        let video_url = format!("https://www.youtube.com/watch?v={}", video_id);
+        match youtube::video::fetch_initial_player_response(youtube, &video_url).await {
+            Ok(ipr) => streams.push(ipr),
+            // A single video has to work, but a playlist can have broken ones
+            Err(e) if video_ids.len() == 1 => {
+                return Err(ErrorInternalServerError(format!("{:?}", e)))
+            }
+            Err(e) => warn!("Skipping {} of the playlist: {:?}", video_id, e),
+        }
+    }
+
+    // Create the tasks and broadcast them to the bus
+    let mut channel_pictures = HashMap::new();
+    let mut created = vec![];
+    let single = streams.len() == 1;
+    for ipr in streams {
+        let video_id = ipr.video_details.video_id.clone();
+        let task = match create_task(youtube, ipr, &taskreq, &mut channel_pictures).await {
+            Ok(task) => task,
+            Err(e) if single => return Err(ErrorInternalServerError(format!("{:?}", e))),
+            // Earlier entries were already queued, so keep going
+            Err(e) => {
+                warn!("Skipping {} of the playlist: {:?}", video_id, e);
+                continue;
+            }
+        };
+        created.push(task.video_id.clone());
+        tx.send(Message::ToRecord(task))
+            .await
+            .map_err(|e| ErrorInternalServerError(format!("{:?}", e)))?;
+    }
+
+    Ok(HttpResponse::Accepted().json(CreateTaskResponse { video_ids: created }))
 }
 
 #[get("/api/version")]
@@ -109,19 +267,34 @@ async fn get_version() -> actix_web::Result<impl Responder> {
     Ok(HttpResponse::Ok().body(crate::APP_NAME.to_owned()))
 }
 
+#[get("/api/youtube/stats")]
+async fn get_youtube_stats() -> actix_web::Result<impl Responder> {
+    Ok(HttpResponse::Ok().json(youtube::shared().stats()))
+}
+
 #[get("/api/config")]
 async fn get_config(config: Data<Arc<RwLock<Config>>>) -> actix_web::Result<impl Responder> {
     Ok(HttpResponse::Ok().json(config.read().await.to_owned()))
 }
 
+/// Reports a failed config reload as a system event.
+async fn report_reload_failure(tx: &BusTx<Message>, e: &anyhow::Error) {
+    let event = SystemEvent::new(SystemEventKind::ConfigReloadFailed, format!("{:#}", e));
+    if let Err(e) = tx.send(Message::SystemEvent(event)).await {
+        warn!("Failed to report config reload failure: {}", e);
+    }
+}
+
 #[post("/api/config/reload")]
-async fn reload_config(config: Data<Arc<RwLock<Config>>>) -> actix_web::Result<impl Responder> {
-    config
-        .write()
-        .await
-        .reload()
-        .await
-        .map_err(|e| ErrorInternalServerError(format!("{:?}", e)))?;
+async fn reload_config(
+    config: Data<Arc<RwLock<Config>>>,
+    tx: Data<BusTx<Message>>,
+) -> actix_web::Result<impl Responder> {
+    let res = config.write().await.reload().await;
+    if let Err(e) = res {
+        report_reload_failure(&tx, &e).await;
+        return Err(ErrorInternalServerError(format!("{:?}", e)));
+    }
     Ok(HttpResponse::Ok().json("ok"))
 }
 
@@ -140,18 +313,89 @@ async fn get_config_toml(config: Data<Arc<RwLock<Config>>>) -> actix_web::Result
 #[put("/api/config/toml")]
 async fn put_config_toml(
     config: Data<Arc<RwLock<Config>>>,
+    tx: Data<BusTx<Message>>,
     body: web::Bytes,
 ) -> actix_web::Result<impl Responder> {
     let body = std::str::from_utf8(&body).map_err(|e| ErrorBadRequest(format!("{:?}", e)))?;
-    config
-        .write()
-        .await
-        .set_source_toml(body)
-        .await
-        .map_err(|e| ErrorBadRequest(format!("{:?}", e)))?;
+    let res = config.write().await.set_source_toml(body).await;
+    if let Err(e) = res {
+        report_reload_failure(&tx, &e).await;
+        return Err(ErrorBadRequest(format!("{:?}", e)));
+    }
     Ok(HttpResponse::Ok().json("ok"))
 }
 
+#[derive(Deserialize, TS)]
+#[ts(export, export_to = "web/src/bindings/")]
+struct TestFilterRequest {
+    expression: String,
+    /// Sample video to test the expression against.
+    video: Option<filter::Video>,
+    /// If provided, the video details are fetched from YouTube instead.
+    video_url: Option<String>,
+}
+
+#[derive(Serialize, TS)]
+#[ts(export, export_to = "web/src/bindings/")]
+struct TestFilterResponse {
+    /// Whether the video matches, or null if the video is missing details
+    /// needed to decide.
+    matched: Option<bool>,
+    video: filter::Video,
+}
+
+#[post("/api/filters/test")]
+async fn test_filter(testreq: web::Json<TestFilterRequest>) -> actix_web::Result<impl Responder> {
+    let testreq = testreq.into_inner();
+    let filter =
+        Filter::parse(&testreq.expression).map_err(|e| ErrorBadRequest(format!("{:?}", e)))?;
+
+    let video = match (testreq.video_url, testreq.video) {
+        (Some(video_url), _) => {
+            let url =
+                youtube::URL::parse(&video_url).map_err(|e| ErrorBadRequest(format!("{:?}", e)))?;
+            let video_id = url
+                .video_id()
+                .ok_or(ErrorBadRequest(anyhow!("Not a video URL")))?;
+            let video_url = format!("https://www.youtube.com/watch?v={}", video_id);
+            let ipr = youtube::video::fetch_initial_player_response(youtube::shared(), &video_url)
+                .await
+                .map_err(|e| ErrorInternalServerError(format!("{:?}", e)))?;
+            filter::Video::from(&ipr)
+        }
+        (None, Some(video)) => video,
+        (None, None) => return Err(ErrorBadRequest("Either video or video_url is required")),
+    };
+
+    Ok(HttpResponse::Ok().json(TestFilterResponse {
+        matched: filter.eval(&video),
+        video,
+    }))
+}
+
+#[derive(Deserialize, TS)]
+#[ts(export, export_to = "web/src/bindings/")]
+struct ResolveChannelRequest {
+    /// A channel ID, `@handle` or channel URL.
+    channel: String,
+}
+
+#[post("/api/channels/resolve")]
+async fn resolve_channel(
+    req: web::Json<ResolveChannelRequest>,
+) -> actix_web::Result<impl Responder> {
+    let req = req.into_inner();
+
+    // Make sure it looks like a channel before asking YouTube
+    youtube::channel::channel_page_url(&req.channel)
+        .map_err(|e| ErrorBadRequest(format!("{:?}", e)))?;
+    let channel = youtube::channel::resolve(youtube::shared(), &req.channel)
+        .await
+        .map_err(|e| ErrorInternalServerError(format!("{:?}", e)))?;
+
+    Ok(HttpResponse::Ok().json(channel))
+}
+
 #[get("/{_:.*}")]
 async fn serve_static(path: web::Path<String>) -> impl Responder {
     let mut path = path.into_inner();
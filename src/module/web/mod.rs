@@ -1,4 +1,7 @@
-use super::{recorder::YTAStatus, Message, Module, Task};
+use super::{
+    recorder::{YTAState, YTAStatus},
+    Duplicate, Message, Module, PostProcessStatus, ScheduledTask, Task,
+};
 use crate::{
     config::{Config, WebserverConfig},
     msgbus::BusTx,
@@ -15,6 +18,7 @@ use tokio::{
 };
 use ts_rs::TS;
 
+mod calendar;
 mod handler;
 
 pub struct WebServer {
@@ -28,7 +32,59 @@ pub struct TaskWithStatus {
     pub status: YTAStatus,
 }
 
+#[derive(Debug, Clone, TS, Serialize)]
+#[ts(export, export_to = "web/src/bindings/")]
+pub struct ScheduleEntry {
+    pub task: Task,
+    /// When the stream starts, if known.
+    pub starts_at: Option<chrono::DateTime<chrono::Utc>>,
+    /// When ytarchive will be started, or null if it's already waiting for
+    /// the stream.
+    pub spawn_at: Option<chrono::DateTime<chrono::Utc>>,
+}
+
+/// Returns the upcoming streams, both scheduled and already waited on by
+/// ytarchive, ordered by start time.
+async fn get_schedule(tasks: &TaskMap, scheduled: &ScheduleMap) -> Vec<ScheduleEntry> {
+    let mut entries = scheduled
+        .read()
+        .await
+        .values()
+        .map(|s| ScheduleEntry {
+            task: s.task.clone(),
+            starts_at: s.task.scheduled_start,
+            spawn_at: Some(s.spawn_at),
+        })
+        .collect::<Vec<_>>();
+
+    for t in tasks.read().await.values() {
+        if let YTAState::Waiting(start) = t.status.state() {
+            if entries.iter().any(|e| e.task.video_id == t.task.video_id) {
+                continue;
+            }
+            entries.push(ScheduleEntry {
+                task: t.task.clone(),
+                starts_at: start.or(t.task.scheduled_start),
+                spawn_at: None,
+            });
+        }
+    }
+
+    // Unknown start times go last
+    entries.sort_by_key(|e| (e.starts_at.is_none(), e.starts_at));
+    entries
+}
+
 type TaskMap = Data<RwLock<HashMap<String, TaskWithStatus>>>;
+type DuplicateList = Data<RwLock<Vec<Duplicate>>>;
+type ScheduleMap = Data<RwLock<HashMap<String, ScheduledTask>>>;
+type PostProcessList = Data<RwLock<Vec<PostProcessStatus>>>;
+
+/// Maximum number of duplicates to keep track of.
+const MAX_DUPLICATES: usize = 100;
+
+/// Maximum number of post-processing statuses to keep track of.
+const MAX_POSTPROCESS: usize = 200;
 
 impl WebServer {
     /// Return the webserver configuration
@@ -41,11 +97,15 @@ impl WebServer {
         &self,
         rx: &mut mpsc::Receiver<Message>,
         tasks: TaskMap,
+        duplicates: DuplicateList,
+        scheduled: ScheduleMap,
+        postprocess: PostProcessList,
     ) -> Result<()> {
         while let Some(msg) = rx.recv().await {
             match msg {
                 Message::RecordingStatus(recstat) => {
                     let id = recstat.task.video_id.clone();
+                    scheduled.write().await.remove(&id);
                     let mut tasks = tasks.write().await;
                     tasks.insert(
                         id,
@@ -55,6 +115,28 @@ impl WebServer {
                         },
                     );
                 }
+                Message::Duplicate(duplicate) => {
+                    let mut duplicates = duplicates.write().await;
+                    if duplicates.len() >= MAX_DUPLICATES {
+                        duplicates.remove(0);
+                    }
+                    duplicates.push(duplicate);
+                }
+                Message::Scheduled(task) => {
+                    let id = task.task.video_id.clone();
+                    scheduled.write().await.insert(id, task);
+                }
+                Message::PostProcess(status) => {
+                    let mut postprocess = postprocess.write().await;
+                    // Only keep the latest status of each step
+                    postprocess.retain(|s| {
+                        s.task.video_id != status.task.video_id || s.step != status.step
+                    });
+                    if postprocess.len() >= MAX_POSTPROCESS {
+                        postprocess.remove(0);
+                    }
+                    postprocess.push(status);
+                }
                 _ => (),
             }
         }
@@ -83,9 +165,18 @@ impl Module for WebServer {
 
         // Create a HashMap to hold the tasks
         let tasks = Data::new(RwLock::new(HashMap::new()));
+        let duplicates = Data::new(RwLock::new(Vec::new()));
+        let scheduled = Data::new(RwLock::new(HashMap::new()));
+        let postprocess = Data::new(RwLock::new(Vec::new()));
 
         // Listen to the bus
-        let busll = self.bus_listen_loop(rx, tasks.clone());
+        let busll = self.bus_listen_loop(
+            rx,
+            tasks.clone(),
+            duplicates.clone(),
+            scheduled.clone(),
+            postprocess.clone(),
+        );
 
         // Set up webserver
         let config = Data::new(self.config.clone());
@@ -96,6 +187,9 @@ impl Module for WebServer {
                     .app_data(config.clone())
                     .app_data(tx.clone())
                     .app_data(tasks.clone())
+                    .app_data(duplicates.clone())
+                    .app_data(scheduled.clone())
+                    .app_data(postprocess.clone())
                     .configure(handler::configure)
             })
             .disable_signals();
@@ -0,0 +1,347 @@
+//! Templates for the paths of finished recordings, e.g.
+//! `{channel_name}/{upload_date:%Y-%m}/{title} [{video_id}].{ext}`.
+//!
+//! Placeholders are replaced with details of the recording, `/` separates
+//! directories, and `{{` / `}}` produce literal braces. Dates take an optional
+//! strftime format after a colon. Every path component is sanitized so the
+//! result is valid on Windows, macOS and Linux.
+
+use anyhow::{anyhow, bail, Result};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+
+/// Maximum length of a single path component in bytes. Most file systems
+/// allow 255, some room is left for collision suffixes.
+const MAX_COMPONENT_LEN: usize = 200;
+
+const DEFAULT_DATE_FORMAT: &str = "%Y%m%d";
+
+/// A parsed output path template. Serializes back to its source string.
+#[derive(Debug, Clone)]
+pub struct OutputTemplate {
+    source: String,
+    parts: Vec<Part>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Part {
+    Literal(String),
+    Field(Field),
+    Date(DateField, String),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Field {
+    Title,
+    VideoId,
+    ChannelName,
+    ChannelId,
+    /// The file name ytarchive chose, without the extension.
+    Filename,
+    Ext,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum DateField {
+    /// When the stream was scheduled to start, or when it was recorded.
+    UploadDate,
+    /// When the recording finished.
+    Date,
+}
+
+/// Details of a recording to fill into a template.
+pub struct Vars<'a> {
+    pub title: &'a str,
+    pub video_id: &'a str,
+    pub channel_name: &'a str,
+    pub channel_id: &'a str,
+    pub filename: &'a str,
+    pub ext: &'a str,
+    pub upload_date: DateTime<Utc>,
+    pub date: DateTime<Utc>,
+}
+
+impl OutputTemplate {
+    pub fn parse(source: &str) -> Result<Self> {
+        if source.starts_with('/') || source.starts_with('\\') {
+            bail!("Template must be relative to the output directory");
+        }
+        if source.ends_with('/') {
+            bail!("Template must end with a file name");
+        }
+
+        let mut parts = vec![];
+        let mut literal = String::new();
+        let mut chars = source.chars().peekable();
+        while let Some(c) = chars.next() {
+            match c {
+                '{' if chars.peek() == Some(&'{') => {
+                    chars.next();
+                    literal.push('{');
+                }
+                '}' if chars.peek() == Some(&'}') => {
+                    chars.next();
+                    literal.push('}');
+                }
+                '{' => {
+                    let mut placeholder = String::new();
+                    loop {
+                        match chars.next() {
+                            Some('}') => break,
+                            Some(c) => placeholder.push(c),
+                            None => bail!("Unclosed placeholder {{{}", placeholder),
+                        }
+                    }
+                    if !literal.is_empty() {
+                        parts.push(Part::Literal(std::mem::take(&mut literal)));
+                    }
+                    parts.push(parse_placeholder(&placeholder)?);
+                }
+                '}' => bail!("Unmatched }}, use }}}} for a literal brace"),
+                c => literal.push(c),
+            }
+        }
+        if !literal.is_empty() {
+            parts.push(Part::Literal(literal));
+        }
+
+        Ok(Self {
+            source: source.to_string(),
+            parts,
+        })
+    }
+
+    /// Renders the template into a path relative to the output directory.
+    /// The extension is appended if the template doesn't contain `{ext}`.
+    pub fn render(&self, vars: &Vars) -> PathBuf {
+        let mut rendered = String::new();
+        for part in &self.parts {
+            match part {
+                Part::Literal(s) => rendered.push_str(s),
+                Part::Field(field) => rendered.push_str(&sanitize_value(match field {
+                    Field::Title => vars.title,
+                    Field::VideoId => vars.video_id,
+                    Field::ChannelName => vars.channel_name,
+                    Field::ChannelId => vars.channel_id,
+                    Field::Filename => vars.filename,
+                    Field::Ext => vars.ext,
+                })),
+                Part::Date(field, format) => {
+                    let date = match field {
+                        DateField::UploadDate => vars.upload_date,
+                        DateField::Date => vars.date,
+                    };
+                    rendered.push_str(&sanitize_value(&date.format(format).to_string()));
+                }
+            }
+        }
+        if !vars.ext.is_empty() && !self.parts.contains(&Part::Field(Field::Ext)) {
+            rendered.push('.');
+            rendered.push_str(vars.ext);
+        }
+
+        rendered.split('/').map(sanitize_component).collect()
+    }
+}
+
+fn parse_placeholder(placeholder: &str) -> Result<Part> {
+    let (name, format) = match placeholder.split_once(':') {
+        Some((name, format)) => (name.trim(), Some(format)),
+        None => (placeholder.trim(), None),
+    };
+
+    let date = match name {
+        "upload_date" => Some(DateField::UploadDate),
+        "date" => Some(DateField::Date),
+        _ => None,
+    };
+    if let Some(date) = date {
+        let format = format.unwrap_or(DEFAULT_DATE_FORMAT);
+        if chrono::format::StrftimeItems::new(format)
+            .any(|item| item == chrono::format::Item::Error)
+        {
+            bail!("Invalid date format {:?} in {{{}}}", format, placeholder);
+        }
+        return Ok(Part::Date(date, format.to_string()));
+    }
+
+    let field = match name {
+        "title" => Field::Title,
+        "video_id" | "id" => Field::VideoId,
+        "channel_name" | "channel" => Field::ChannelName,
+        "channel_id" => Field::ChannelId,
+        "filename" => Field::Filename,
+        "ext" => Field::Ext,
+        _ => return Err(anyhow!("Unknown placeholder {{{}}}", name)),
+    };
+    if format.is_some() {
+        bail!("Only dates can be formatted, in {{{}}}", placeholder);
+    }
+    Ok(Part::Field(field))
+}
+
+/// Replaces characters that aren't allowed in file names, so a value can't
+/// introduce new path components.
+fn sanitize_value(value: &str) -> String {
+    value
+        .chars()
+        .map(|c| match c {
+            '/' | '\\' | ':' | '*' | '?' | '"' | '<' | '>' | '|' => '_',
+            c if c.is_control() => '_',
+            c => c,
+        })
+        .collect()
+}
+
+/// Makes a single path component safe to use on any platform.
+fn sanitize_component(component: &str) -> String {
+    let mut component = sanitize_value(component)
+        .trim()
+        .trim_end_matches('.')
+        .to_string();
+    if component.is_empty() || component.chars().all(|c| c == '.') {
+        return "_".to_string();
+    }
+
+    // Names reserved by Windows, even with an extension
+    let stem = component.split('.').next().unwrap_or_default();
+    let reserved = matches!(
+        stem.to_ascii_uppercase().as_str(),
+        "CON" | "PRN" | "AUX" | "NUL"
+    ) || (stem.len() == 4
+        && stem.is_ascii()
+        && ["COM", "LPT"].contains(&stem[..3].to_ascii_uppercase().as_str())
+        && stem.as_bytes()[3].is_ascii_digit());
+    if reserved {
+        component.insert(0, '_');
+    }
+
+    if component.len() > MAX_COMPONENT_LEN {
+        // Shorten the name but keep the extension
+        let ext = match component.rsplit_once('.') {
+            Some((_, ext)) if ext.len() < 16 => format!(".{}", ext),
+            _ => String::new(),
+        };
+        let mut end = MAX_COMPONENT_LEN - ext.len();
+        while !component.is_char_boundary(end) {
+            end -= 1;
+        }
+        component = format!("{}{}", component[..end].trim_end(), ext);
+    }
+    component
+}
+
+/// Returns the path, or if it already exists, the first of `name (1).ext`,
+/// `name (2).ext`, ... that doesn't.
+pub fn unique_path(path: &Path) -> PathBuf {
+    if !path.exists() {
+        return path.to_path_buf();
+    }
+    let stem = path
+        .file_stem()
+        .map(|s| s.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let ext = path
+        .extension()
+        .map(|e| format!(".{}", e.to_string_lossy()))
+        .unwrap_or_default();
+    (1..)
+        .map(|i| path.with_file_name(format!("{} ({}){}", stem, i, ext)))
+        .find(|p| !p.exists())
+        .expect("ran out of file names")
+}
+
+impl std::fmt::Display for OutputTemplate {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.source)
+    }
+}
+
+impl Serialize for OutputTemplate {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        serializer.serialize_str(&self.source)
+    }
+}
+
+impl<'de> Deserialize<'de> for OutputTemplate {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        let s = String::deserialize(deserializer)?;
+        OutputTemplate::parse(&s).map_err(|e| serde::de::Error::custom(format!("{}: {}", s, e)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{OutputTemplate, Vars};
+    use chrono::TimeZone;
+    use std::path::PathBuf;
+
+    fn vars() -> Vars<'static> {
+        Vars {
+            title: "【Karaoke】Singing: songs / more?",
+            video_id: "abc123",
+            channel_name: "Moona Hoshinova",
+            channel_id: "UCP0BspO_AMEe3aQqqpo89Dg",
+            filename: "20220101 Karaoke",
+            ext: "mp4",
+            upload_date: chrono::Utc.ymd(2022, 3, 14).and_hms(12, 0, 0),
+            date: chrono::Utc.ymd(2022, 3, 15).and_hms(1, 2, 3),
+        }
+    }
+
+    fn render(template: &str) -> PathBuf {
+        OutputTemplate::parse(template).unwrap().render(&vars())
+    }
+
+    #[test]
+    fn test_render() {
+        assert_eq!(
+            render("{channel_name}/{upload_date:%Y-%m}/{title} [{video_id}].{ext}"),
+            PathBuf::from("Moona Hoshinova/2022-03/【Karaoke】Singing_ songs _ more_ [abc123].mp4")
+        );
+        assert_eq!(
+            render("{upload_date} {{{id}}}"),
+            PathBuf::from("20220314 {abc123}.mp4")
+        );
+        assert_eq!(
+            render("{date:%H%M%S}/{filename}"),
+            PathBuf::from("010203/20220101 Karaoke.mp4")
+        );
+    }
+
+    #[test]
+    fn test_sanitize() {
+        assert_eq!(render("../{id}"), PathBuf::from("_/abc123.mp4"));
+        assert_eq!(render("con/aux.{ext}"), PathBuf::from("_con/_aux.mp4"));
+        assert_eq!(render("dir. /{id}"), PathBuf::from("dir/abc123.mp4"));
+        assert_eq!(render("com1/lpt9.{ext}"), PathBuf::from("_com1/_lpt9.mp4"));
+        // Four bytes, but not four characters
+        assert_eq!(render("#歌/aあ.{ext}"), PathBuf::from("#歌/aあ.mp4"));
+        assert_eq!(render("com歌/{id}"), PathBuf::from("com歌/abc123.mp4"));
+        let long = render(&"a".repeat(300));
+        assert_eq!(long.to_string_lossy().len(), 200);
+        assert!(long.to_string_lossy().ends_with(".mp4"));
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        for template in [
+            "/abs/{id}",
+            "{id",
+            "id}",
+            "{nope}",
+            "{title:%Y}",
+            "{date:%Q}",
+            "dir/",
+        ] {
+            assert!(OutputTemplate::parse(template).is_err(), "{}", template);
+        }
+    }
+}
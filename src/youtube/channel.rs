@@ -1,25 +1,38 @@
+use super::{
+    video::{self, InitialPlayerResponse},
+    YouTube, URL,
+};
 use anyhow::{anyhow, Context, Result};
 use lazy_static::lazy_static;
-use reqwest::Client;
+use regex::Regex;
+use serde::Serialize;
+use ts_rs::TS;
 
-pub async fn fetch_picture_url(client: Client, channel_id: &str) -> Result<String> {
+lazy_static! {
+    static ref PICTURE_RE: Regex =
+        Regex::new(r#"<meta name="twitter:image" content="(.*?)""#).unwrap();
+    static ref CHANNEL_ID_RE: Regex = Regex::new(r"^UC[\w-]{22}$").unwrap();
+}
+
+/// A channel found from a handle, vanity URL or ID.
+#[derive(Debug, Clone, PartialEq, Serialize, TS)]
+#[ts(export, export_to = "web/src/bindings/")]
+pub struct ResolvedChannel {
+    pub id: String,
+    pub name: String,
+    pub picture_url: Option<String>,
+}
+
+pub async fn fetch_picture_url(youtube: &YouTube, channel_id: &str) -> Result<String> {
     // Fetch the channel page
     let channel_url = format!("https://www.youtube.com/channel/{}", channel_id);
-    let res = client
-        .get(&channel_url)
-        .send()
-        .await
-        .context("Failed to fetch channel page")?
-        .text()
+    let res = youtube
+        .get_text(&channel_url)
         .await
-        .context("Failed to read channel page")?;
+        .context("Failed to fetch channel page")?;
 
     // Find the picture URL
-    lazy_static! {
-        static ref RE: regex::Regex =
-            regex::Regex::new(r#"<meta name="twitter:image" content="(.*?)""#).unwrap();
-    }
-    let captures = RE
+    let captures = PICTURE_RE
         .captures(&res)
         .ok_or_else(|| anyhow!("Could not find picture URL"))?;
     let picture_url = captures
@@ -30,3 +43,141 @@ pub async fn fetch_picture_url(client: Client, channel_id: &str) -> Result<Strin
 
     Ok(picture_url.to_owned())
 }
+
+/// Returns whether the string is a channel ID, like `UCP0BspO_AMEe3aQqqpo89Dg`.
+pub fn is_channel_id(s: &str) -> bool {
+    CHANNEL_ID_RE.is_match(s)
+}
+
+/// Returns the page of a channel given as an ID, `@handle` or any channel URL.
+pub fn channel_page_url(channel: &str) -> Result<String> {
+    let channel = channel.trim();
+    if is_channel_id(channel) {
+        return Ok(format!("https://www.youtube.com/channel/{}", channel));
+    }
+    if channel.starts_with('@') && !channel.contains('/') {
+        return Ok(format!("https://www.youtube.com/{}", channel));
+    }
+
+    let url = URL::parse(channel).map_err(|e| anyhow!("Not a channel: {}", e))?;
+    let path = if let Some(id) = url.channel_id() {
+        format!("channel/{}", id)
+    } else if let Some(handle) = url.channel_handle() {
+        handle
+    } else if let Some(vanity) = url.channel_vanity() {
+        format!("c/{}", vanity)
+    } else if let Some(user) = url.channel_user() {
+        format!("user/{}", user)
+    } else {
+        return Err(anyhow!("Not a channel URL: {}", channel));
+    };
+    Ok(format!("https://www.youtube.com/{}", path))
+}
+
+/// Finds the ID, name and picture of a channel given as an ID, `@handle` or
+/// any channel URL.
+pub async fn resolve(youtube: &YouTube, channel: &str) -> Result<ResolvedChannel> {
+    let url = channel_page_url(channel)?;
+    let html = youtube
+        .get_text(&url)
+        .await
+        .with_context(|| format!("Failed to fetch channel page of {}", channel))?;
+    let resolved =
+        parse_channel_page(&html).with_context(|| format!("Failed to resolve {}", channel))?;
+    debug!(
+        "Resolved {} to {} ({})",
+        channel, resolved.id, resolved.name
+    );
+    Ok(resolved)
+}
+
+/// Returns the stream the channel is live with right now, if any.
+pub async fn fetch_live(youtube: &YouTube, channel: &str) -> Result<Option<InitialPlayerResponse>> {
+    // The live page of a channel is the stream it's live with, or the channel
+    // page otherwise
+    let url = format!("{}/live", channel_page_url(channel)?);
+    let html = youtube
+        .get_text(&url)
+        .await
+        .with_context(|| format!("Failed to fetch live page of {}", channel))?;
+    Ok(video::parse_initial_player_response(&html)?.filter(|ipr| ipr.video_details.is_live))
+}
+
+/// Reads the channel details from the meta tags of a channel page.
+fn parse_channel_page(html: &str) -> Result<ResolvedChannel> {
+    lazy_static! {
+        static ref CANONICAL_RE: Regex = Regex::new(
+            r#"<link rel="canonical" href="https://www\.youtube\.com/channel/(UC[\w-]{22})""#
+        )
+        .unwrap();
+        static ref TITLE_RE: Regex =
+            Regex::new(r#"<meta property="og:title" content="(.*?)""#).unwrap();
+    }
+    let capture = |re: &Regex| {
+        re.captures(html)
+            .and_then(|c| c.get(1))
+            .map(|m| unescape(m.as_str()))
+    };
+    Ok(ResolvedChannel {
+        id: capture(&CANONICAL_RE).ok_or_else(|| anyhow!("Could not find the channel ID"))?,
+        name: capture(&TITLE_RE).ok_or_else(|| anyhow!("Could not find the channel name"))?,
+        picture_url: capture(&PICTURE_RE),
+    })
+}
+
+/// Decodes the HTML entities that can appear in attributes.
+fn unescape(s: &str) -> String {
+    s.replace("&quot;", "\"")
+        .replace("&#39;", "'")
+        .replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&amp;", "&")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{channel_page_url, parse_channel_page};
+
+    #[test]
+    fn test_channel_page_url() {
+        let url = |s| channel_page_url(s).unwrap();
+        assert_eq!(
+            url("UCP0BspO_AMEe3aQqqpo89Dg"),
+            "https://www.youtube.com/channel/UCP0BspO_AMEe3aQqqpo89Dg"
+        );
+        assert_eq!(
+            url("@moonahoshinova"),
+            "https://www.youtube.com/@moonahoshinova"
+        );
+        assert_eq!(
+            url("https://www.youtube.com/@moonahoshinova/streams"),
+            "https://www.youtube.com/@moonahoshinova"
+        );
+        assert_eq!(
+            url("https://youtube.com/c/MoonaHoshinovahololive"),
+            "https://www.youtube.com/c/MoonaHoshinovahololive"
+        );
+        assert_eq!(
+            url("https://www.youtube.com/user/Moona"),
+            "https://www.youtube.com/user/Moona"
+        );
+        assert!(channel_page_url("moona").is_err());
+        assert!(channel_page_url("https://www.youtube.com/watch?v=stmZAThUl64").is_err());
+    }
+
+    #[test]
+    fn test_parse_channel_page() {
+        let html = r#"<html><head><link rel="canonical" href="https://www.youtube.com/channel/UCP0BspO_AMEe3aQqqpo89Dg">
+<meta property="og:title" content="Moona Hoshinova hololive-ID &amp; friends">
+<meta name="twitter:image" content="https://yt3.ggpht.com/picture=s900"></head>"#;
+        let channel = parse_channel_page(html).unwrap();
+        assert_eq!(channel.id, "UCP0BspO_AMEe3aQqqpo89Dg");
+        assert_eq!(channel.name, "Moona Hoshinova hololive-ID & friends");
+        assert_eq!(
+            channel.picture_url.as_deref(),
+            Some("https://yt3.ggpht.com/picture=s900")
+        );
+
+        assert!(parse_channel_page("<html>This channel does not exist.</html>").is_err());
+    }
+}
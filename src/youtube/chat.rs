@@ -0,0 +1,199 @@
+use super::YouTube;
+use anyhow::{anyhow, Context, Result};
+use lazy_static::lazy_static;
+use regex::Regex;
+use serde_json::{json, Value};
+use std::time::Duration;
+
+/// How long to wait between requests if YouTube doesn't say.
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// A live chat being read through the innertube API, as used by the chat
+/// popout on the watch page.
+pub struct LiveChat {
+    youtube: YouTube,
+    api_key: String,
+    client_version: String,
+    continuation: Option<String>,
+}
+
+/// A page of chat actions, as returned by `get_live_chat`.
+pub struct ChatPage {
+    /// The raw actions, such as new messages, deletions and pinned banners.
+    pub actions: Vec<Value>,
+    /// How long YouTube wants us to wait before the next request.
+    pub timeout: Duration,
+}
+
+impl LiveChat {
+    /// Loads the chat popout of a live stream to find where to start reading
+    /// the chat from.
+    pub async fn connect(youtube: YouTube, video_id: &str) -> Result<Self> {
+        let url = format!(
+            "https://www.youtube.com/live_chat?is_popout=1&v={}",
+            video_id
+        );
+        let html = youtube
+            .get_text(&url)
+            .await
+            .context("Failed to fetch live chat page")?;
+
+        let (api_key, client_version, continuation) = parse_chat_page(&html)?;
+        Ok(Self {
+            youtube,
+            api_key,
+            client_version,
+            continuation: Some(continuation),
+        })
+    }
+
+    /// Fetches the next page of chat actions. Returns `None` once the chat has
+    /// ended.
+    pub async fn poll(&mut self) -> Result<Option<ChatPage>> {
+        let continuation = match &self.continuation {
+            Some(continuation) => continuation,
+            None => return Ok(None),
+        };
+        let url = format!(
+            "https://www.youtube.com/youtubei/v1/live_chat/get_live_chat?key={}",
+            self.api_key
+        );
+        let body = json!({
+            "context": {
+                "client": {
+                    "clientName": "WEB",
+                    "clientVersion": self.client_version,
+                },
+            },
+            "continuation": continuation,
+        });
+        let res = self
+            .youtube
+            .post_json(&url, &body)
+            .await
+            .context("Failed to fetch live chat")?;
+
+        let (page, continuation) = parse_chat_response(&res);
+        self.continuation = continuation;
+        Ok(page)
+    }
+}
+
+/// Finds the API key, client version and the continuation of the full (not
+/// "top") chat in the chat popout page.
+fn parse_chat_page(html: &str) -> Result<(String, String, String)> {
+    lazy_static! {
+        static ref API_KEY_RE: Regex = Regex::new(r#""INNERTUBE_API_KEY":"([^"]+)""#).unwrap();
+        static ref VERSION_RE: Regex =
+            Regex::new(r#""INNERTUBE_CONTEXT_CLIENT_VERSION":"([^"]+)""#).unwrap();
+        static ref DATA_RE: Regex =
+            Regex::new(r#"(?s)ytInitialData"?\]?\s*=\s*(\{.*?\});\s*</script>"#).unwrap();
+    }
+    let capture = |re: &Regex, what: &str| {
+        re.captures(html)
+            .and_then(|c| c.get(1))
+            .map(|m| m.as_str().to_string())
+            .ok_or_else(|| anyhow!("Failed to find {} in live chat page", what))
+    };
+    let api_key = capture(&API_KEY_RE, "the API key")?;
+    let client_version = capture(&VERSION_RE, "the client version")?;
+    let data: Value = serde_json::from_str(&capture(&DATA_RE, "the initial data")?)
+        .context("Failed to parse the initial data of the live chat")?;
+
+    let renderer = &data["contents"]["liveChatRenderer"];
+    if renderer.is_null() {
+        return Err(anyhow!("Live chat is not available"));
+    }
+
+    // The second view of the selector is the full chat, the first one and
+    // the default continuation are the top chat
+    let full_chat = renderer["header"]["liveChatHeaderRenderer"]["viewSelector"]
+        ["sortFilterSubMenuRenderer"]["subMenuItems"][1]["continuation"]["reloadContinuationData"]
+        ["continuation"]
+        .as_str()
+        .map(String::from);
+    let continuation = full_chat
+        .or_else(|| continuation_of(&renderer["continuations"]).map(|(c, _)| c))
+        .ok_or_else(|| anyhow!("Failed to find the live chat continuation"))?;
+
+    Ok((api_key, client_version, continuation))
+}
+
+/// Reads the actions and the next continuation from a `get_live_chat`
+/// response.
+fn parse_chat_response(res: &Value) -> (Option<ChatPage>, Option<String>) {
+    let chat = &res["continuationContents"]["liveChatContinuation"];
+    if chat.is_null() {
+        return (None, None);
+    }
+
+    let actions = chat["actions"].as_array().cloned().unwrap_or_default();
+    let (continuation, timeout) = match continuation_of(&chat["continuations"]) {
+        Some((continuation, timeout)) => (Some(continuation), timeout),
+        None => (None, None),
+    };
+    let page = ChatPage {
+        actions,
+        timeout: timeout.unwrap_or(DEFAULT_TIMEOUT),
+    };
+    (Some(page), continuation)
+}
+
+/// Returns the first continuation token in a list of continuations, along with
+/// the timeout YouTube asks for. Each entry holds one kind of continuation,
+/// such as `invalidationContinuationData` or `timedContinuationData`.
+fn continuation_of(continuations: &Value) -> Option<(String, Option<Duration>)> {
+    continuations
+        .as_array()?
+        .iter()
+        .filter_map(|c| c.as_object()?.values().next())
+        .find_map(|data| {
+            let continuation = data["continuation"].as_str()?.to_string();
+            let timeout = data["timeoutMs"].as_u64().map(Duration::from_millis);
+            Some((continuation, timeout))
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_chat_page, parse_chat_response};
+    use serde_json::json;
+    use std::time::Duration;
+
+    #[test]
+    fn test_parse_chat_page() {
+        let html = r#"<script>ytcfg.set({"INNERTUBE_API_KEY":"key","INNERTUBE_CONTEXT_CLIENT_VERSION":"2.2022"});</script>
+<script>window["ytInitialData"] = {"contents":{"liveChatRenderer":{
+"continuations":[{"invalidationContinuationData":{"continuation":"top","timeoutMs":10000}}],
+"header":{"liveChatHeaderRenderer":{"viewSelector":{"sortFilterSubMenuRenderer":{"subMenuItems":[
+{"continuation":{"reloadContinuationData":{"continuation":"top"}}},
+{"continuation":{"reloadContinuationData":{"continuation":"all"}}}]}}}}}}};</script>"#;
+        let (key, version, continuation) = parse_chat_page(html).unwrap();
+        assert_eq!(key, "key");
+        assert_eq!(version, "2.2022");
+        assert_eq!(continuation, "all");
+
+        assert!(parse_chat_page(
+            r#""INNERTUBE_API_KEY":"k","INNERTUBE_CONTEXT_CLIENT_VERSION":"v" var ytInitialData = {"contents":{"messageRenderer":{}}};</script>"#
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_parse_chat_response() {
+        let res = json!({"continuationContents": {"liveChatContinuation": {
+            "continuations": [{"timedContinuationData": {"continuation": "next", "timeoutMs": 2500}}],
+            "actions": [{"addChatItemAction": {}}, {"markChatItemAsDeletedAction": {}}],
+        }}});
+        let (page, continuation) = parse_chat_response(&res);
+        let page = page.unwrap();
+        assert_eq!(page.actions.len(), 2);
+        assert_eq!(page.timeout, Duration::from_millis(2500));
+        assert_eq!(continuation.as_deref(), Some("next"));
+
+        // The chat has ended
+        let (page, continuation) = parse_chat_response(&json!({"responseContext": {}}));
+        assert!(page.is_none());
+        assert!(continuation.is_none());
+    }
+}
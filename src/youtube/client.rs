@@ -0,0 +1,202 @@
+use crate::{
+    config::{Config, NetworkConfig, YouTubeConfig},
+    network,
+};
+use anyhow::{anyhow, bail, Context, Result};
+use chrono::{DateTime, Utc};
+use reqwest::{header, RequestBuilder, Response, StatusCode};
+use serde::Serialize;
+use std::sync::{Arc, Mutex, OnceLock};
+use ts_rs::TS;
+
+/// Cookie that declines the cookie consent, which is what YouTube sets when
+/// clicking "Reject all".
+const CONSENT_COOKIE: &str = "SOCS=CAI";
+
+/// The client shared by every module, set up at startup.
+static SHARED: OnceLock<YouTube> = OnceLock::new();
+
+/// A client for requests to YouTube, which retries failed requests, gets past
+/// the cookie consent and keeps count of the requests made.
+#[derive(Clone)]
+pub struct YouTube {
+    client: reqwest::Client,
+    config: YouTubeConfig,
+    stats: Arc<Mutex<RequestStats>>,
+}
+
+/// Counts of the requests made to YouTube since the start.
+#[derive(Debug, Clone, Default, Serialize, TS)]
+#[ts(export, export_to = "web/src/bindings/")]
+pub struct RequestStats {
+    /// Requests sent, including retries.
+    pub requests: u64,
+    /// Requests that were retried.
+    pub retries: u64,
+    /// Requests that still failed after all retries.
+    pub failures: u64,
+    /// Responses telling us to slow down.
+    pub rate_limited: u64,
+    /// Responses that were the cookie consent page instead.
+    pub consent_pages: u64,
+    pub last_error: Option<String>,
+    pub last_error_at: Option<DateTime<Utc>>,
+}
+
+/// Sets up the shared client from the config. Changes to the config apply
+/// after a restart.
+pub fn init(config: &Config) -> Result<()> {
+    let youtube = YouTube::new(&config.youtube, &config.network)?;
+    SHARED
+        .set(youtube)
+        .map_err(|_| anyhow!("YouTube client is already set up"))
+}
+
+/// Returns the shared client.
+pub fn shared() -> &'static YouTube {
+    SHARED.get().expect("YouTube client is not set up")
+}
+
+/// Returns the shared client, or a new one for the config if it isn't set up
+/// yet, e.g. while loading the config at startup.
+pub fn for_config(config: &Config) -> Result<YouTube> {
+    match SHARED.get() {
+        Some(youtube) => Ok(youtube.clone()),
+        None => YouTube::new(&config.youtube, &config.network),
+    }
+}
+
+impl YouTube {
+    fn new(config: &YouTubeConfig, network: &NetworkConfig) -> Result<Self> {
+        Ok(Self {
+            client: build_client(config, network)?,
+            config: config.clone(),
+            stats: Arc::new(Mutex::new(RequestStats::default())),
+        })
+    }
+
+    /// Returns a client that goes through another network, with the same
+    /// settings and counting its requests along with this one.
+    pub fn with_network(&self, network: &NetworkConfig) -> Result<Self> {
+        Ok(Self {
+            client: build_client(&self.config, network)?,
+            config: self.config.clone(),
+            stats: self.stats.clone(),
+        })
+    }
+
+    pub fn stats(&self) -> RequestStats {
+        self.stats.lock().expect("stats lock poisoned").clone()
+    }
+
+    /// Fetches a page, failing if it returns an error.
+    pub async fn get(&self, url: &str) -> Result<Response> {
+        self.send(|client| client.get(url)).await
+    }
+
+    /// Fetches a page as text.
+    pub async fn get_text(&self, url: &str) -> Result<String> {
+        self.get(url)
+            .await?
+            .text()
+            .await
+            .with_context(|| format!("Failed to read {}", url))
+    }
+
+    /// Posts JSON to an API, returning the JSON response.
+    pub async fn post_json(
+        &self,
+        url: &str,
+        body: &serde_json::Value,
+    ) -> Result<serde_json::Value> {
+        self.send(|client| client.post(url).json(body))
+            .await?
+            .json()
+            .await
+            .with_context(|| format!("Failed to parse response of {}", url))
+    }
+
+    /// Sends a request, retrying it if it fails in a way that might not
+    /// happen again.
+    async fn send(&self, request: impl Fn(&reqwest::Client) -> RequestBuilder) -> Result<Response> {
+        let mut delay = self.config.retry_delay;
+        let mut attempt = 0;
+        loop {
+            let mut req = request(&self.client)
+                .build()
+                .context("Failed to build request")?;
+            let url = req.url().to_string();
+            let is_youtube = req
+                .url()
+                .host_str()
+                .map(|host| host == "youtube.com" || host.ends_with(".youtube.com"))
+                .unwrap_or(false);
+            if self.config.consent_cookie && is_youtube {
+                req.headers_mut().insert(
+                    header::COOKIE,
+                    header::HeaderValue::from_static(CONSENT_COOKIE),
+                );
+            }
+
+            self.record(|s| s.requests += 1);
+            let res = self.client.execute(req).await;
+            let retry = match &res {
+                Ok(res) if res.status() == StatusCode::TOO_MANY_REQUESTS => {
+                    self.record(|s| s.rate_limited += 1);
+                    true
+                }
+                Ok(res) => res.status().is_server_error(),
+                Err(e) => e.is_timeout() || e.is_connect(),
+            };
+
+            if retry && attempt < self.config.retries {
+                attempt += 1;
+                self.record(|s| s.retries += 1);
+                debug!(
+                    "Retrying {} in {:?} ({}/{})",
+                    url, delay, attempt, self.config.retries
+                );
+                tokio::time::sleep(delay).await;
+                delay *= 2;
+                continue;
+            }
+
+            let res = res
+                .with_context(|| format!("Failed to fetch {}", url))
+                .and_then(|res| {
+                    res.error_for_status()
+                        .with_context(|| format!("{} returned an error", url))
+                })
+                .and_then(|res| {
+                    if is_consent_page(&res) {
+                        self.record(|s| s.consent_pages += 1);
+                        bail!("{} redirected to the cookie consent page", url);
+                    }
+                    Ok(res)
+                });
+            if let Err(e) = &res {
+                self.record(|s| {
+                    s.failures += 1;
+                    s.last_error = Some(format!("{:#}", e));
+                    s.last_error_at = Some(Utc::now());
+                });
+            }
+            return res;
+        }
+    }
+
+    fn record(&self, update: impl FnOnce(&mut RequestStats)) {
+        update(&mut self.stats.lock().expect("stats lock poisoned"));
+    }
+}
+
+fn build_client(config: &YouTubeConfig, network: &NetworkConfig) -> Result<reqwest::Client> {
+    network::client_builder(network)?
+        .timeout(config.timeout)
+        .build()
+        .context("Failed to create client")
+}
+
+fn is_consent_page(res: &Response) -> bool {
+    res.url().host_str() == Some("consent.youtube.com")
+}
@@ -1,13 +1,19 @@
 use url::Url;
 
 pub mod channel;
+pub mod chat;
+pub mod client;
+pub mod playlist;
 pub mod video;
 
+pub use client::{init, shared, YouTube};
+
 /// Parses a YouTube URL and returns its details. Returns an Err if the URL
 /// could not be parsed, or if it's not a supported URL. Supported URLs are:
 ///
 /// - Videos (youtube.com/watch?v=... and youtu.be/...)
-/// - Channels (youtube.com/channel/..., youtube.com/c/...)
+/// - Channels (youtube.com/channel/..., youtube.com/c/..., youtube.com/user/...
+///   and youtube.com/@...)
 /// - Playlists (youtube.com/playlist?list=...)
 #[derive(Debug)]
 pub struct URL {
@@ -86,6 +92,25 @@ impl URL {
         }
     }
 
+    pub fn channel_user(&self) -> Option<String> {
+        let mut segs = self.parsed_uri.path_segments()?;
+
+        match segs.next()? {
+            "user" => Some(segs.next()?.to_string()),
+            _ => None,
+        }
+    }
+
+    /// Returns the handle of a youtube.com/@... URL, including the `@`.
+    pub fn channel_handle(&self) -> Option<String> {
+        let mut segs = self.parsed_uri.path_segments()?;
+
+        match segs.next()? {
+            handle if handle.len() > 1 && handle.starts_with('@') => Some(handle.to_string()),
+            _ => None,
+        }
+    }
+
     pub fn playlist_id(&self) -> Option<String> {
         return self
             .parsed_uri
@@ -141,5 +166,23 @@ mod tests {
                 .channel_vanity(),
             Some("loudnessfete".into()),
         );
+        assert_eq!(
+            URL::parse("https://www.youtube.com/user/Moona")
+                .expect("Should parse")
+                .channel_user(),
+            Some("Moona".into()),
+        );
+        assert_eq!(
+            URL::parse("https://www.youtube.com/@moonahoshinova/streams")
+                .expect("Should parse")
+                .channel_handle(),
+            Some("@moonahoshinova".into()),
+        );
+        assert_eq!(
+            URL::parse("https://www.youtube.com/watch?v=stmZAThUl64")
+                .expect("Should parse")
+                .channel_handle(),
+            None,
+        );
     }
 }
@@ -0,0 +1,135 @@
+use super::YouTube;
+use anyhow::{anyhow, Context, Result};
+use lazy_static::lazy_static;
+use regex::Regex;
+use serde_json::Value;
+
+/// A video in a playlist.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PlaylistEntry {
+    pub video_id: String,
+    pub title: String,
+    pub status: EntryStatus,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum EntryStatus {
+    Live,
+    Upcoming,
+    /// A regular video, or a stream that has ended.
+    Video,
+}
+
+impl PlaylistEntry {
+    pub fn is_live_or_upcoming(&self) -> bool {
+        self.status != EntryStatus::Video
+    }
+}
+
+/// Fetches the entries of a playlist. Only the first page of the playlist is
+/// read, which holds up to 100 videos.
+pub async fn fetch_entries(youtube: &YouTube, playlist_id: &str) -> Result<Vec<PlaylistEntry>> {
+    let url = format!("https://www.youtube.com/playlist?list={}", playlist_id);
+    let html = youtube
+        .get_text(&url)
+        .await
+        .context("Failed to fetch playlist page")?;
+    parse_playlist_page(&html).with_context(|| format!("Failed to read playlist {}", playlist_id))
+}
+
+/// Finds the videos in the initial data of a playlist page.
+fn parse_playlist_page(html: &str) -> Result<Vec<PlaylistEntry>> {
+    lazy_static! {
+        static ref DATA_RE: Regex =
+            Regex::new(r#"(?s)ytInitialData"?\]?\s*=\s*(\{.*?\});\s*</script>"#).unwrap();
+    }
+    let data = DATA_RE
+        .captures(html)
+        .and_then(|c| c.get(1))
+        .ok_or_else(|| anyhow!("Failed to find the initial data"))?;
+    let data: Value =
+        serde_json::from_str(data.as_str()).context("Failed to parse the initial data")?;
+
+    if data["alerts"][0]["alertRenderer"]["type"] == "ERROR" {
+        return Err(anyhow!("Playlist does not exist or is private"));
+    }
+
+    let mut renderers = vec![];
+    find_renderers(&data, "playlistVideoRenderer", &mut renderers);
+    Ok(renderers.into_iter().filter_map(parse_entry).collect())
+}
+
+/// Collects every object under the given key, wherever it is in the data.
+fn find_renderers<'a>(value: &'a Value, key: &str, found: &mut Vec<&'a Value>) {
+    match value {
+        Value::Object(map) => {
+            for (k, v) in map {
+                if k == key {
+                    found.push(v);
+                } else {
+                    find_renderers(v, key, found);
+                }
+            }
+        }
+        Value::Array(values) => values.iter().for_each(|v| find_renderers(v, key, found)),
+        _ => (),
+    }
+}
+
+fn parse_entry(renderer: &Value) -> Option<PlaylistEntry> {
+    // Deleted and private videos are still listed
+    if renderer["isPlayable"] == false {
+        return None;
+    }
+
+    let video_id = renderer["videoId"].as_str()?.to_string();
+    let title = renderer["title"]["runs"][0]["text"]
+        .as_str()
+        .or_else(|| renderer["title"]["simpleText"].as_str())
+        .unwrap_or_default()
+        .to_string();
+    let is_live = renderer["thumbnailOverlays"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .any(|o| o["thumbnailOverlayTimeStatusRenderer"]["style"] == "LIVE");
+    let status = if is_live {
+        EntryStatus::Live
+    } else if renderer["upcomingEventData"].is_object() {
+        EntryStatus::Upcoming
+    } else {
+        EntryStatus::Video
+    };
+
+    Some(PlaylistEntry {
+        video_id,
+        title,
+        status,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_playlist_page, EntryStatus};
+
+    #[test]
+    fn test_parse_playlist_page() {
+        let html = r#"<script nonce="x">var ytInitialData = {"contents":{"twoColumnBrowseResultsRenderer":{"tabs":[{"tabRenderer":{"content":{"sectionListRenderer":{"contents":[{"itemSectionRenderer":{"contents":[{"playlistVideoListRenderer":{"contents":[
+{"playlistVideoRenderer":{"videoId":"aaaaaaaaaaa","title":{"runs":[{"text":"Part 1"}]},"isPlayable":true,"thumbnailOverlays":[{"thumbnailOverlayTimeStatusRenderer":{"style":"DEFAULT"}}]}},
+{"playlistVideoRenderer":{"videoId":"bbbbbbbbbbb","title":{"runs":[{"text":"Part 2"}]},"isPlayable":true,"thumbnailOverlays":[{"thumbnailOverlayTimeStatusRenderer":{"style":"LIVE"}}]}},
+{"playlistVideoRenderer":{"videoId":"ccccccccccc","title":{"simpleText":"Part 3"},"isPlayable":true,"upcomingEventData":{"startTime":"1700000000"}}},
+{"playlistVideoRenderer":{"videoId":"ddddddddddd","title":{"runs":[{"text":"[Private video]"}]},"isPlayable":false}}
+]}}]}}]}}}}]}}};</script>"#;
+        let entries = parse_playlist_page(html).unwrap();
+        assert_eq!(entries.len(), 3);
+        assert_eq!(entries[0].video_id, "aaaaaaaaaaa");
+        assert_eq!(entries[0].status, EntryStatus::Video);
+        assert_eq!(entries[1].status, EntryStatus::Live);
+        assert_eq!(entries[2].title, "Part 3");
+        assert_eq!(entries[2].status, EntryStatus::Upcoming);
+
+        let html =
+            r#"var ytInitialData = {"alerts":[{"alertRenderer":{"type":"ERROR"}}]};</script>"#;
+        assert!(parse_playlist_page(html).is_err());
+    }
+}
@@ -1,12 +1,16 @@
+use super::YouTube;
 use actix_web::http::Uri;
 use anyhow::{anyhow, Context, Result};
-use reqwest::Client;
+use chrono::{DateTime, Utc};
 use serde::Deserialize;
 
 #[derive(Deserialize)]
 pub struct InitialPlayerResponse {
     #[serde(rename = "videoDetails")]
     pub video_details: InitialPlayerResponseVideoDetails,
+    #[serde(rename = "playabilityStatus")]
+    pub playability_status: Option<InitialPlayerResponsePlayabilityStatus>,
+    pub microformat: Option<InitialPlayerResponseMicroformat>,
 }
 #[derive(Deserialize)]
 pub struct InitialPlayerResponseVideoDetails {
@@ -17,6 +21,44 @@ pub struct InitialPlayerResponseVideoDetails {
     pub channel_id: String,
     pub author: String,
     pub thumbnail: InitialPlayerResponseVideoDetailsThumbnail,
+    #[serde(rename = "lengthSeconds")]
+    pub length_seconds: Option<String>,
+    #[serde(default)]
+    pub keywords: Vec<String>,
+    #[serde(rename = "shortDescription", default)]
+    pub short_description: String,
+    #[serde(rename = "isLive", default)]
+    pub is_live: bool,
+    #[serde(rename = "isUpcoming", default)]
+    pub is_upcoming: bool,
+}
+#[derive(Deserialize)]
+pub struct InitialPlayerResponsePlayabilityStatus {
+    pub reason: Option<String>,
+    #[serde(default)]
+    pub messages: Vec<String>,
+}
+#[derive(Deserialize)]
+pub struct InitialPlayerResponseMicroformat {
+    #[serde(rename = "playerMicroformatRenderer")]
+    pub player_microformat_renderer: InitialPlayerResponseMicroformatRenderer,
+}
+#[derive(Deserialize)]
+pub struct InitialPlayerResponseMicroformatRenderer {
+    #[serde(rename = "liveBroadcastDetails")]
+    pub live_broadcast_details: Option<InitialPlayerResponseLiveBroadcastDetails>,
+    pub category: Option<String>,
+    #[serde(rename = "publishDate")]
+    pub publish_date: Option<String>,
+    #[serde(rename = "ownerProfileUrl")]
+    pub owner_profile_url: Option<String>,
+}
+#[derive(Deserialize)]
+pub struct InitialPlayerResponseLiveBroadcastDetails {
+    #[serde(rename = "startTimestamp")]
+    pub start_timestamp: Option<DateTime<Utc>>,
+    #[serde(rename = "endTimestamp")]
+    pub end_timestamp: Option<DateTime<Utc>>,
 }
 #[derive(Deserialize)]
 pub struct InitialPlayerResponseVideoDetailsThumbnail {
@@ -29,8 +71,52 @@ pub struct InitialPlayerResponseVideoDetailsThumbnailThumbnail {
     pub height: u32,
 }
 
+impl InitialPlayerResponse {
+    /// Returns the duration of the video, if known. Streams that are live or
+    /// upcoming report a length of zero, which is treated as unknown.
+    pub fn duration(&self) -> Option<std::time::Duration> {
+        self.video_details
+            .length_seconds
+            .as_ref()
+            .and_then(|s| s.parse().ok())
+            .filter(|&s| s > 0)
+            .map(std::time::Duration::from_secs)
+    }
+
+    /// Returns true if the video can only be watched by channel members.
+    pub fn is_members_only(&self) -> bool {
+        let status = match &self.playability_status {
+            Some(status) => status,
+            None => return false,
+        };
+        status
+            .reason
+            .iter()
+            .chain(status.messages.iter())
+            .any(|m| m.to_lowercase().contains("members"))
+    }
+
+    /// Returns the scheduled (or actual) start time of a live stream.
+    pub fn start_time(&self) -> Option<DateTime<Utc>> {
+        self.live_broadcast_details()?.start_timestamp
+    }
+
+    /// Returns when a live stream ended.
+    pub fn end_time(&self) -> Option<DateTime<Utc>> {
+        self.live_broadcast_details()?.end_timestamp
+    }
+
+    fn live_broadcast_details(&self) -> Option<&InitialPlayerResponseLiveBroadcastDetails> {
+        self.microformat
+            .as_ref()?
+            .player_microformat_renderer
+            .live_broadcast_details
+            .as_ref()
+    }
+}
+
 pub async fn fetch_initial_player_response(
-    client: Client,
+    youtube: &YouTube,
     url: &str,
 ) -> Result<InitialPlayerResponse> {
     // Parse URL
@@ -43,33 +129,44 @@ pub async fn fetch_initial_player_response(
     }
 
     // Fetch the video URL
-    let html = client
-        .get(url)
-        .send()
+    let html = youtube
+        .get_text(url)
         .await
-        .context("Failed to fetch video page")?
-        .error_for_status()
-        .context("Video page returned error")?
-        .text()
-        .await
-        .context("Failed to read video page response")?;
+        .context("Failed to fetch video page")?;
+
+    parse_initial_player_response(&html)?
+        .ok_or(anyhow!("Failed to find the initial player response"))
+}
 
-    // Parse page contents
+/// Reads the initial player response from a page, if it has one. Pages that
+/// aren't of a video, like channel pages, don't.
+pub fn parse_initial_player_response(html: &str) -> Result<Option<InitialPlayerResponse>> {
     lazy_static::lazy_static! {
         static ref IPR_RE: regex::Regex =
             regex::Regex::new(r#"ytInitialPlayerResponse = (.*?});"#).unwrap();
     }
 
-    let ipr = IPR_RE
-        .captures(&html)
-        .ok_or(anyhow!("Failed to find the initial player response"))?
-        .get(1)
-        .ok_or(anyhow!("Failed to find the initial player response"))?
-        .as_str();
+    let ipr = match IPR_RE.captures(html).and_then(|c| c.get(1)) {
+        Some(ipr) => ipr.as_str(),
+        None => return Ok(None),
+    };
 
     // Parse the initial player response
     let ipr: InitialPlayerResponse =
         serde_json::from_str(ipr).context("Failed to parse the initial player response")?;
 
-    Ok(ipr)
+    Ok(Some(ipr))
+}
+
+/// Downloads the highest resolution thumbnail of a video, falling back to the
+/// given URL if there is none.
+pub async fn fetch_thumbnail(youtube: &YouTube, video_id: &str, fallback: &str) -> Result<Vec<u8>> {
+    let maxres = format!("https://i.ytimg.com/vi/{}/maxresdefault.jpg", video_id);
+    for url in [maxres.as_str(), fallback] {
+        match youtube.get(url).await {
+            Ok(res) => return Ok(res.bytes().await?.to_vec()),
+            Err(e) => debug!("Failed to fetch thumbnail {}: {:#}", url, e),
+        }
+    }
+    Err(anyhow!("Failed to fetch thumbnail of {}", video_id))
 }
@@ -3,11 +3,13 @@ import { YTAState } from '../bindings/YTAState';
 import { TaskWithStatus } from '../bindings/TaskWithStatus';
 import { rejectError } from './api';
 import { CreateTaskRequest } from '../bindings/CreateTaskRequest';
+import { CreateTaskResponse } from '../bindings/CreateTaskResponse';
 
 export const stateString = (state: YTAState) => {
   if (typeof state === 'object' && 'Waiting' in state)
     return 'Waiting (' + state.Waiting + ')';
   else if (state === 'AlreadyProcessed') return 'Already Processed';
+  else if (state === 'LoginRequired') return 'Login Required';
   else return state;
 };
 export const stateKey = (state: YTAState) =>
@@ -24,6 +26,7 @@ const stateSort: ReturnType<typeof stateKey>[] = [
   'Ended',
   'AlreadyProcessed',
   'Interrupted',
+  'LoginRequired',
 ];
 export const useQueryTasks = () =>
   useQuery(
@@ -52,7 +55,9 @@ export const useMutateCreateTask = () => {
         method: 'POST',
         headers: { 'Content-Type': 'application/json' },
         body: JSON.stringify(task),
-      }).then(rejectError),
+      })
+        .then(rejectError)
+        .then((res) => res.json() as Promise<CreateTaskResponse>),
     {
       onSuccess: () => {
         queryClient.invalidateQueries(['tasks']);
@@ -39,7 +39,9 @@ const TaskStateBadge = ({ state }: { state: YTAState }) => (
         ? 'yellow'
         : state === 'Idle' || state === 'AlreadyProcessed' || state === 'Ended'
         ? 'gray'
-        : state === 'Interrupted' || state === 'Errored'
+        : state === 'Interrupted' ||
+          state === 'LoginRequired' ||
+          state === 'Errored'
         ? 'red'
         : 'violet'
     }
@@ -66,10 +68,32 @@ const rowElements = ({ task, status }: TaskWithStatus) => [
       {task.channel_name}
     </Anchor>
   </>,
-  <TaskStateBadge state={status.state} />,
+  <>
+    <TaskStateBadge state={status.state} />
+    {status.mode === 'audio_only' && (
+      <Text size="xs" color="dimmed">
+        Audio only
+      </Text>
+    )}
+    {status.mode === 'video_only' && (
+      <Text size="xs" color="dimmed">
+        Video only
+      </Text>
+    )}
+    {status.vod && (
+      <Text size="xs" color="dimmed">
+        VOD
+      </Text>
+    )}
+  </>,
   <>
     {status.total_size === null ? (
       'None'
+    ) : status.vod ? (
+      <>
+        {status.progress === null ? '?' : status.progress.toFixed(1)}% of{' '}
+        {status.total_size}
+      </>
     ) : (
       <>
         V: {status.video_fragments || '?'} / A: {status.audio_fragments || '?'}{' '}
@@ -106,12 +130,25 @@ const AddVideoModal = () => {
       {
         video_url: videoURL,
         output_directory: destPath,
+        all_entries: false,
+        // Use the settings of the channel saving to the directory
+        quality: null,
+        args: [],
+        threads: null,
+        mode: null,
+        output_template: null,
       },
       {
-        onSuccess() {
+        onSuccess(data) {
+          const count = data.video_ids.length;
           showNotification({
-            message: 'Video added',
-            color: 'green',
+            message:
+              count === 1
+                ? 'Video added'
+                : count === 0
+                ? 'No live or upcoming videos found'
+                : count + ' videos added',
+            color: count === 0 ? 'yellow' : 'green',
           });
         },
         async onError(err) {
@@ -133,7 +170,7 @@ const AddVideoModal = () => {
   return (
     <Stack spacing="md">
       <TextInput
-        label="Video URL"
+        label="Video, playlist or channel URL"
         placeholder="https://www.youtube.com/watch?v=..."
         data-autofocus
         value={videoURL}